@@ -1,18 +1,90 @@
 #[macro_use]
 extern crate debug_unreachable;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
 
 
-use std::cmp;
+use std::borrow::Cow;
+use std::cmp::{self, Ordering};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::iter::{self, FusedIterator};
 use std::mem;
+use std::ops::{Bound, ControlFlow, RangeBounds};
+use std::ptr;
+use std::slice;
+use std::str;
+use std::vec;
 
 
 fn nybble<K: AsRef<[u8]>>(idx: usize, key: K) -> u8 {
     let byte = key.as_ref()[idx >> 1];
 
-    if idx & 1 == 0 { byte & 0x0F } else { byte >> 4 }
+    if idx & 1 == 0 { byte >> 4 } else { byte & 0x0F }
 }
 
 
+/// Get the nybble at the given index of a key, or `None` if the key is too short to have one.
+fn try_nybble<K: AsRef<[u8]>>(idx: usize, key: K) -> Option<u8> {
+    if idx >> 1 < key.as_ref().len() {
+        Some(nybble(idx, key))
+    } else {
+        None
+    }
+}
+
+
+/// Compare the first `nybbles` nybbles of two keys, as the keys themselves would be compared. A key
+/// too short to have them all sorts by what it has.
+fn cmp_nybble_prefix(left: &[u8], right: &[u8], nybbles: usize) -> Ordering {
+    let bytes = nybbles / 2;
+    let left_bytes = &left[..cmp::min(left.len(), bytes)];
+    let right_bytes = &right[..cmp::min(right.len(), bytes)];
+
+    match left_bytes.cmp(right_bytes) {
+        Ordering::Equal if nybbles & 1 == 1 => {
+            try_nybble(nybbles - 1, left).cmp(&try_nybble(nybbles - 1, right))
+        }
+        ordering => ordering,
+    }
+}
+
+
+/// Find the index of the first nybble at which two keys differ, or `None` if they are equal.
+fn nybble_mismatch(left: &[u8], right: &[u8]) -> Option<usize> {
+    let min_length = cmp::min(left.len(), right.len());
+
+    for i in 0..min_length {
+        let difference = left[i] ^ right[i];
+
+        // If the difference is nonzero, we've found a differing byte in our keys!
+        if difference != 0 {
+            return Some(if difference & 0xF0 == 0 {
+                            // If `difference & 0xF0` is zero, then the difference is strictly in
+                            // the lower nybble. Thus we increment the nybble index.
+
+                            2 * i + 1
+                        } else {
+                            2 * i
+                        });
+        }
+    }
+
+    // There is no difference in the overlapping bytes of our keys. If their lengths are the same,
+    // then they're guaranteed to be the same because their overlapping bytes are all of their
+    // bytes, each.
+    if left.len() == right.len() {
+        None
+    } else {
+        Some(2 * min_length)
+    }
+}
+
+
+#[derive(Clone)]
 pub struct Sparse<T> {
     index: u32,
     data: Vec<T>,
@@ -57,38 +129,114 @@ impl<T> Sparse<T> {
     }
 
 
+    /// Get mutable references to the elements at two distinct indices, if both are present.
+    pub fn get_two_mut(&mut self, idx1: usize, idx2: usize) -> Option<(&mut T, &mut T)> {
+        debug_assert!(idx1 != idx2);
+
+        if self.contains(idx1) && self.contains(idx2) {
+            let (actual1, actual2) = (self.actual(idx1), self.actual(idx2));
+
+            if actual1 < actual2 {
+                let (low, high) = self.data.split_at_mut(actual2);
+                Some((&mut low[actual1], &mut high[0]))
+            } else {
+                let (low, high) = self.data.split_at_mut(actual1);
+                Some((&mut high[0], &mut low[actual2]))
+            }
+        } else {
+            None
+        }
+    }
+
+
     pub fn insert_fresh(&mut self, idx: usize, elt: T) {
         debug_assert!(!self.contains(idx));
 
         let actual = self.actual(idx);
         self.data.insert(actual, elt);
+        self.index |= 1 << idx;
+    }
+
+
+    /// Remove the element with the greatest index, if there is one.
+    fn pop(&mut self) -> Option<T> {
+        let last = self.data.pop()?;
+        self.index &= !(1 << (31 - self.index.leading_zeros()));
+        Some(last)
+    }
+
+
+    pub fn remove(&mut self, idx: usize) -> Option<T> {
+        if self.contains(idx) {
+            let actual = self.actual(idx);
+            self.index &= !(1 << idx);
+            Some(self.data.remove(actual))
+        } else {
+            None
+        }
     }
 }
 
 
+impl<T> Default for Sparse<T> {
+    fn default() -> Sparse<T> {
+        Sparse::new()
+    }
+}
+
+
+#[derive(Clone)]
 pub struct Leaf<K, V> {
     key: K,
     val: V,
 }
 
 
+/// Copy the given bytes into a fresh key.
+///
+/// # Panics
+///
+/// Panics if the number of bytes differs from the length of a `K`.
+fn key_from_bytes<K: AsMut<[u8]> + Copy, L: AsRef<[u8]>>(key_bytes: L) -> K {
+    unsafe {
+        let mut key: K = mem::zeroed();
+        key.as_mut().copy_from_slice(key_bytes.as_ref());
+        key
+    }
+}
+
+
 impl<K: AsMut<[u8]> + Copy, V> Leaf<K, V> {
     pub fn new<L: AsRef<[u8]>>(key_bytes: L, val: V) -> Leaf<K, V> {
         Leaf {
-            key: unsafe {
-                let mut key: K = mem::uninitialized();
-                key.as_mut().copy_from_slice(key_bytes.as_ref());
-                key
-            },
+            key: key_from_bytes(key_bytes),
             val,
         }
     }
 }
 
 
+impl<K, V> Leaf<K, V> {
+    /// The key stored in this leaf.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+
+    /// The value stored in this leaf.
+    pub fn val(&self) -> &V {
+        &self.val
+    }
+}
+
+
+#[derive(Clone)]
 pub struct Internal<K, V> {
     index: usize,
     nybbles: Sparse<Node<K, V>>,
+    // The number of leaves beneath this node, as of the last time the prefix count cache was
+    // built.
+    count: usize,
 }
 
 
@@ -97,8 +245,27 @@ impl<K, V> Internal<K, V> {
         Internal {
             index,
             nybbles: Sparse::new(),
+            count: 0,
         }
     }
+
+
+    /// The index of the nybble at which the keys beneath this node branch apart.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+
+    /// The child holding the keys whose nybble at this node's index is `nybble`, if any.
+    pub fn child(&self, nybble: u8) -> Option<&Node<K, V>> {
+        self.nybbles.get(nybble as usize)
+    }
+
+
+    /// Iterate over the children of this node, in order of their nybbles.
+    pub fn children(&self) -> slice::Iter<'_, Node<K, V>> {
+        self.nybbles.data.iter()
+    }
 }
 
 
@@ -107,9 +274,17 @@ impl<K: AsRef<[u8]>, V> Internal<K, V> {
         self.nybbles
             .insert_fresh(nybble(self.index, &leaf.key) as usize, Node::Leaf(leaf));
     }
+
+
+    /// Insert a node holding `key` under the nybble its keys share at this node's index, which no
+    /// other child may occupy.
+    fn insert_fresh_child(&mut self, key: &K, child: Node<K, V>) {
+        self.nybbles.insert_fresh(nybble(self.index, key) as usize, child);
+    }
 }
 
 
+#[derive(Clone)]
 pub enum Node<K, V> {
     Leaf(Leaf<K, V>),
     Internal(Internal<K, V>),
@@ -131,161 +306,5843 @@ impl<K, V> Node<K, V> {
             _ => unsafe { debug_unreachable!() },
         }
     }
+
+
+    fn compare_structure(&self, other: &Node<K, V>) -> bool
+        where K: PartialEq
+    {
+        match (self, other) {
+            (Node::Leaf(leaf), Node::Leaf(other)) => leaf.key == other.key,
+            (Node::Internal(internal), Node::Internal(other)) => {
+                internal.index == other.index && internal.nybbles.index == other.nybbles.index &&
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .zip(&other.nybbles.data)
+                    .all(|(child, other)| child.compare_structure(other))
+            }
+            _ => false,
+        }
+    }
+
+
+    /// Pass each leaf beneath this node to `f` in order.
+    fn for_each_leaf<'a, F: FnMut(&'a Leaf<K, V>)>(&'a self, f: &mut F) {
+        match *self {
+            Node::Leaf(ref leaf) => f(leaf),
+            Node::Internal(ref internal) => {
+                for child in &internal.nybbles.data {
+                    child.for_each_leaf(f);
+                }
+            }
+        }
+    }
+
+
+    /// Pass each leaf beneath this node to `f` in order, skipping the rest of a leaf's siblings or
+    /// stopping altogether as `f` directs. Only a leaf passes `SkipSubtrie` up to its parent.
+    fn depth_first_mut<F>(&mut self, f: &mut F) -> DepthFirstControl
+        where F: FnMut(&K, &mut V) -> DepthFirstControl
+    {
+        match *self {
+            Node::Leaf(ref mut leaf) => f(&leaf.key, &mut leaf.val),
+            Node::Internal(ref mut internal) => {
+                for child in &mut internal.nybbles.data {
+                    match child.depth_first_mut(f) {
+                        DepthFirstControl::Continue => {}
+                        DepthFirstControl::SkipSubtrie => break,
+                        DepthFirstControl::Stop => return DepthFirstControl::Stop,
+                    }
+                }
+
+                DepthFirstControl::Continue
+            }
+        }
+    }
+
+
+    /// Pass each leaf beneath this node to `f` in order, stopping at the first error.
+    fn try_for_each_leaf_mut<E, F>(&mut self, f: &mut F) -> Result<(), E>
+        where F: FnMut(&mut Leaf<K, V>) -> Result<(), E>
+    {
+        match *self {
+            Node::Leaf(ref mut leaf) => f(leaf),
+            Node::Internal(ref mut internal) => {
+                for child in &mut internal.nybbles.data {
+                    child.try_for_each_leaf_mut(f)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+
+    /// Find the first leaf beneath this node, in order, for which `f` returns `true`.
+    fn find_leaf<'a, F>(&'a self, f: &mut F) -> Option<&'a Leaf<K, V>>
+        where F: FnMut(&'a Leaf<K, V>) -> bool
+    {
+        match *self {
+            Node::Leaf(ref leaf) => if f(leaf) { Some(leaf) } else { None },
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .filter_map(|child| child.find_leaf(f))
+                    .next()
+            }
+        }
+    }
+
+
+    /// Replace every value beneath this node with the result of applying `f` to it, without
+    /// moving any leaf.
+    ///
+    /// # Safety
+    ///
+    /// If `f` panics, the value it was given has already been moved out of its leaf, so this node
+    /// must not be used or dropped afterwards.
+    unsafe fn map_values_in_place<F: FnMut(&K, V) -> V>(&mut self, f: &mut F) {
+        match *self {
+            Node::Leaf(ref mut leaf) => {
+                let val = ptr::read(&leaf.val);
+                ptr::write(&mut leaf.val, f(&leaf.key, val));
+            }
+            Node::Internal(ref mut internal) => {
+                for child in &mut internal.nybbles.data {
+                    child.map_values_in_place(f);
+                }
+            }
+        }
+    }
+
+
+    /// Consume this node, passing each leaf beneath it to `f` in order.
+    fn into_each_leaf<F: FnMut(Leaf<K, V>)>(self, f: &mut F) {
+        match self {
+            Node::Leaf(leaf) => f(leaf),
+            Node::Internal(internal) => {
+                for child in internal.nybbles.data {
+                    child.into_each_leaf(f);
+                }
+            }
+        }
+    }
 }
 
 
 impl<K: AsRef<[u8]>, V> Node<K, V> {
-    /// Get a mutable reference to the closest node to the given key, as well as the first index at
-    /// which the keys differ.
-    fn get_closest_node_mut<L: AsRef<[u8]>>(&mut self,
-                                            key: L,
-                                            index: usize)
-                                            -> (&mut Node<K, V>, usize) {
-        {
-            let closer = self.get_closer_node_mut(key, index);
+    /// Get a reference to the leaf closest to the given key. Where the key has no corresponding
+    /// child, an arbitrary one is taken; all keys in a subtrie share their nybbles up to the
+    /// subtrie's branching index, so the key and the closest leaf differ at the first nybble where
+    /// the key differs from any key in the trie.
+    fn get_closest_leaf<L: AsRef<[u8]>>(&self, key: L) -> &Leaf<K, V> {
+        let mut node = self;
 
-            if closer.is_some() {
-                return closer.unwrap();
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                Node::Internal(ref internal) => {
+                    node = try_nybble(internal.index, &key)
+                        .and_then(|search_nybble| internal.nybbles.get(search_nybble as usize))
+                        .unwrap_or(&internal.nybbles.data[0]);
+                }
             }
         }
+    }
+
 
-        (self, index)
+    /// Get a reference to the leaf reached by following exactly the key's nybbles, if there is one.
+    /// This is the only leaf which could hold the key or any prefix of it.
+    fn get_leaf_on_path<L: AsRef<[u8]>>(&self, key: L) -> Option<&Leaf<K, V>> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return Some(leaf),
+                Node::Internal(ref internal) => {
+                    let search_nybble = try_nybble(internal.index, &key)?;
+                    node = internal.nybbles.get(search_nybble as usize)?;
+                }
+            }
+        }
     }
 
 
-    fn get_closer_node_mut<L: AsRef<[u8]>>(&mut self,
-                                           key: L,
-                                           index: usize)
-                                           -> Option<(&mut Node<K, V>, usize)> {
-        // For some reason this fails due to borrowck weirdness.
+    fn get_leaf_mut<L: AsRef<[u8]>>(&mut self, key: L) -> Option<&mut Leaf<K, V>> {
         match *self {
-            ref mut leaf @ Node::Leaf(..) => return Some((leaf, index)),
+            Node::Leaf(ref mut leaf) => {
+                if leaf.key.as_ref() == key.as_ref() {
+                    Some(leaf)
+                } else {
+                    None
+                }
+            }
 
             Node::Internal(ref mut internal) => {
-                let index = internal.index;
-
-                let search_nybble = nybble(index, &key);
+                let search_nybble = try_nybble(internal.index, &key)?;
 
                 internal
                     .nybbles
-                    .get_mut(search_nybble as usize)
-                    .map(|mut node| node.get_closest_node_mut(key, index))
+                    .get_mut(search_nybble as usize)?
+                    .get_leaf_mut(key)
             }
         }
     }
-}
 
 
-pub struct Trie<K, V> {
-    root: Option<Node<K, V>>,
-}
+    /// Find the values stored for two distinct keys, following their shared path once and
+    /// splitting the borrow where the paths diverge.
+    fn get_two_vals_mut<L1, L2>(&mut self, key1: L1, key2: L2) -> Option<(&mut V, &mut V)>
+        where L1: AsRef<[u8]>,
+              L2: AsRef<[u8]>
+    {
+        match *self {
+            // A single leaf cannot hold both keys.
+            Node::Leaf(..) => None,
 
+            Node::Internal(ref mut internal) => {
+                let nybble1 = try_nybble(internal.index, &key1)? as usize;
+                let nybble2 = try_nybble(internal.index, &key2)? as usize;
 
-impl<K: AsRef<[u8]> + AsMut<[u8]> + Copy, V> Trie<K, V> {
-    pub fn new() -> Trie<K, V> {
-        Trie { root: None }
+                if nybble1 == nybble2 {
+                    internal
+                        .nybbles
+                        .get_mut(nybble1)?
+                        .get_two_vals_mut(key1, key2)
+                } else {
+                    let (child1, child2) = internal.nybbles.get_two_mut(nybble1, nybble2)?;
+                    Some((&mut child1.get_leaf_mut(key1)?.val, &mut child2.get_leaf_mut(key2)?.val))
+                }
+            }
+        }
     }
 
 
-    pub fn insert<L: AsRef<[u8]>>(&mut self, key: L, val: V) -> Option<V> {
-        match self.root {
-            Some(ref mut node) => {
-                let (closest, least_index) = node.get_closest_node_mut(&key, 0);
-
-                let index = match *closest {
-                    Node::Leaf(ref mut leaf) => {
-                        let index = {
-                            let search_key_bytes = key.as_ref();
-                            let leaf_key_bytes = leaf.key.as_ref();
-
-                            let min_length = cmp::min(search_key_bytes.len(), leaf_key_bytes.len());
-
-                            // `Node::get_closest_key_mut` returns not only the closest node, but
-                            // also a conservative "least index" where the keys may begin to
-                            // differ. Thus, we need not start comparing at zero.
-                            let mut i = least_index;
-
-                            loop {
-                                if i >= min_length {
-                                    // There is no difference in the overlapping bytes of our
-                                    // search key and leaf key.
-
-                                    if search_key_bytes.len() == leaf_key_bytes.len() {
-                                        // If their lengths are the same, then we're guaranteed
-                                        // they're the same because their overlapping bytes are all
-                                        // of their bytes, each. We can replace the leaf value and
-                                        // return the displaced value.
-
-                                        return Some(mem::replace(&mut leaf.val, val));
-                                    }
-
-                                    break i;
-                                }
-
-                                let difference = search_key_bytes[i] ^ leaf_key_bytes[i];
-
-                                // If the difference is nonzero, we've found a differing byte in
-                                // our keys!
-                                if difference != 0 {
-                                    break if difference & 0xF0 == 0 {
-                                              // If `difference & 0xF0` is nonzero, then the difference
-                                              // is strictly in the upper nybble. Thus we increment the
-                                              // nybble index.
-
-                                              i + 1
-                                          } else {
-                                              i
-                                          };
-                                }
-
-                                i += 1;
-                            }
-                        };
+    /// Remove the leaf holding the given key from beneath this node, which must be internal. If
+    /// this leaves the node with a single child, the node is replaced by that child.
+    fn remove_child_leaf<L: AsRef<[u8]>>(&mut self, key: L) -> Option<Leaf<K, V>> {
+        let leaf = {
+            let internal = self.mut_unwrap_internal();
+            let search_nybble = try_nybble(internal.index, &key)? as usize;
 
-                        index
-                    }
-                    Node::Internal(ref mut internal) => {
-                        // We can do a "fresh" insert here (that is, safely assume there is no
-                        // value sharing the same key already in this internal node) because if
-                        // there was, then a `Leaf` would have been returned by `closest`.
+            let matches = match *internal.nybbles.get(search_nybble)? {
+                Node::Leaf(ref leaf) => leaf.key.as_ref() == key.as_ref(),
+                Node::Internal(..) => {
+                    return internal
+                               .nybbles
+                               .get_mut(search_nybble)
+                               .and_then(|child| child.remove_child_leaf(key));
+                }
+            };
+
+            if !matches {
+                return None;
+            }
 
-                        internal
-                            .nybbles
-                            .insert_fresh(nybble(internal.index, &key) as usize,
-                                          Node::Leaf(Leaf::new(key, val)));
+            internal.nybbles.remove(search_nybble).map(Node::unwrap_leaf)
+        };
 
+        self.collapse();
+        leaf
+    }
+
+
+    /// Remove the subtrie of keys starting with `prefix` from beneath this node, which must be
+    /// internal and branch before the end of the prefix.
+    fn remove_child_prefix<L: AsRef<[u8]>>(&mut self, prefix: L) -> Option<Node<K, V>> {
+        let subtrie = {
+            let internal = self.mut_unwrap_internal();
+            let search_nybble = nybble(internal.index, &prefix) as usize;
+
+            let descend = match *internal.nybbles.get(search_nybble)? {
+                Node::Internal(ref child) if child.index < 2 * prefix.as_ref().len() => true,
+                ref child => {
+                    if !child.get_closest_leaf(&prefix)
+                             .key
+                             .as_ref()
+                             .starts_with(prefix.as_ref()) {
                         return None;
                     }
-                };
 
-                // If this control reaches this point, then we are guaranteed that `closest` is in
-                // fact a `Node::Leaf`, which has a key which does not match the search key. We
-                // `mem::replace` `closest` with a fresh `internal`, and then unwrap both `closest`
-                // and the `replaced` leaf to get a mutable reference to the new internal and the
-                // bare leaf on the stack.
+                    false
+                }
+            };
 
-                let leaf = mem::replace(closest, Node::Internal(Internal::new(index)))
-                    .unwrap_leaf();
-                let internal = closest.mut_unwrap_internal();
+            if descend {
+                return internal
+                           .nybbles
+                           .get_mut(search_nybble)
+                           .and_then(|child| child.remove_child_prefix(prefix));
+            }
 
-                internal.insert_fresh_leaf(Leaf::new(key, val));
-                internal.insert_fresh_leaf(leaf);
+            internal.nybbles.remove(search_nybble)
+        };
 
-                None
+        self.collapse();
+        subtrie
+    }
+
+
+    /// Remove every leaf beneath this node whose key is also beneath `other`, adding the number
+    /// removed to `removed`, and return what is left. Subtries of either node which cannot share
+    /// any key with the other are never entered.
+    fn difference(mut self, other: &Node<K, V>, removed: &mut usize) -> Option<Node<K, V>> {
+        let (mut internal, other_internal) = match (self, other) {
+            (Node::Leaf(leaf), _) => {
+                let present = other.get_leaf_on_path(&leaf.key)
+                    .is_some_and(|other| other.key.as_ref() == leaf.key.as_ref());
+
+                if present {
+                    *removed += 1;
+                    return None;
+                }
+
+                return Some(Node::Leaf(leaf));
             }
+            (internal, Node::Leaf(other_leaf)) => {
+                self = internal;
+                if self.remove_child_leaf(&other_leaf.key).is_some() {
+                    *removed += 1;
+                }
 
-            // If the root is empty, simply insert a singleton leaf.
-            ref mut none => {
-                *none = Some(Node::Leaf(Leaf::new(key, val)));
-                None
+                return Some(self);
+            }
+            (Node::Internal(internal), Node::Internal(other_internal)) => {
+                (internal, other_internal)
+            }
+        };
+
+        // Both nodes' keys agree with their first leaves up to where they branch, so the first
+        // leaves tell whether those shared prefixes overlap.
+        let shared = nybble_mismatch(internal.nybbles.data[0].get_first_leaf().key.as_ref(),
+                                     other.get_first_leaf().key.as_ref())
+            .unwrap_or(usize::MAX);
+
+        if shared < cmp::min(internal.index, other_internal.index) {
+            return Some(Node::Internal(internal));
+        }
+
+        match internal.index.cmp(&other_internal.index) {
+            // All of `other` lies beneath a single child of this node.
+            Ordering::Less => {
+                let child_nybble = nybble(internal.index, &other.get_first_leaf().key) as usize;
+
+                if let Some(child) = internal.nybbles.remove(child_nybble) {
+                    if let Some(rest) = child.difference(other, removed) {
+                        internal.nybbles.insert_fresh(child_nybble, rest);
+                    }
+                }
+
+                self = Node::Internal(internal);
+                self.collapse();
+                Some(self)
+            }
+
+            // All of this node lies beneath a single child of `other`.
+            Ordering::Greater => {
+                self = Node::Internal(internal);
+                let other_nybble = nybble(other_internal.index, &self.get_first_leaf().key);
+
+                match other_internal.nybbles.get(other_nybble as usize) {
+                    Some(other_child) => self.difference(other_child, removed),
+                    None => Some(self),
+                }
+            }
+
+            Ordering::Equal => {
+                let Internal { index, nybbles: Sparse { index: bitmap, data }, count } = internal;
+                let mut kept = Sparse::new();
+
+                for (child_nybble, child) in (0..16).filter(|n| bitmap >> n & 1 == 1).zip(data) {
+                    let rest = match other_internal.nybbles.get(child_nybble) {
+                        Some(other_child) => child.difference(other_child, removed),
+                        None => Some(child),
+                    };
+
+                    if let Some(rest) = rest {
+                        kept.insert_fresh(child_nybble, rest);
+                    }
+                }
+
+                Node::from_remaining(index, kept, count)
             }
         }
     }
-}
 
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn it_works() {}
+    /// Remove every leaf beneath this node whose key is among `keys`, which must be sorted, adding
+    /// the number removed to `removed`, and return what is left. Each child is only given the run
+    /// of keys which could lie beneath it.
+    fn remove_sorted<L: AsRef<[u8]>>(self,
+                                     keys: &[L],
+                                     removed: &mut usize)
+                                     -> Option<Node<K, V>> {
+        let keys = match self {
+            Node::Leaf(ref leaf) => {
+                let present = keys.binary_search_by(|key| key.as_ref().cmp(leaf.key.as_ref()))
+                    .is_ok();
+
+                if present {
+                    *removed += 1;
+                    return None;
+                }
+
+                return Some(self);
+            }
+            Node::Internal(ref internal) => {
+                // The keys sharing this node's prefix, up to its index, are contiguous.
+                let prefix = internal.nybbles.data[0].get_first_leaf().key.as_ref();
+                let start = keys.partition_point(|key| {
+                    cmp_nybble_prefix(key.as_ref(), prefix, internal.index) == Ordering::Less
+                });
+                let end = keys.partition_point(|key| {
+                    cmp_nybble_prefix(key.as_ref(), prefix, internal.index) != Ordering::Greater
+                });
+
+                &keys[start..end]
+            }
+        };
+
+        if keys.is_empty() {
+            return Some(self);
+        }
+
+        let Internal { index, nybbles: Sparse { index: bitmap, data }, count } = match self {
+            Node::Internal(internal) => internal,
+            Node::Leaf(..) => unsafe { debug_unreachable!() },
+        };
+        let mut kept = Sparse::new();
+        let mut keys = keys;
+
+        for (child_nybble, child) in (0..16).filter(|n| bitmap >> n & 1 == 1).zip(data) {
+            let n = Some(child_nybble as u8);
+            let start = keys.partition_point(|key| try_nybble(index, key) < n);
+            let end = keys.partition_point(|key| try_nybble(index, key) <= n);
+
+            let rest = child.remove_sorted(&keys[start..end], removed);
+            keys = &keys[end..];
+
+            if let Some(rest) = rest {
+                kept.insert_fresh(child_nybble, rest);
+            }
+        }
+
+        Node::from_remaining(index, kept, count)
+    }
+
+
+    /// Check whether every one of `keys`, which must be sorted, is the key of a leaf beneath this
+    /// node, stopping at the first which is not. As in `remove_sorted`, each child is only given
+    /// the run of keys which could lie beneath it.
+    fn contains_sorted<L: AsRef<[u8]>>(&self, keys: &[L]) -> bool {
+        let internal = match *self {
+            Node::Leaf(ref leaf) => {
+                return keys.iter().all(|key| key.as_ref() == leaf.key.as_ref());
+            }
+            Node::Internal(..) if keys.is_empty() => return true,
+            Node::Internal(ref internal) => internal,
+        };
+
+        // The keys are sorted, so if the first and last share this node's prefix, up to its
+        // index, they all do.
+        let prefix = internal.nybbles.data[0].get_first_leaf().key.as_ref();
+        let shared = |key: &L| cmp_nybble_prefix(key.as_ref(), prefix, internal.index) ==
+                               Ordering::Equal;
+        if !shared(&keys[0]) || !shared(&keys[keys.len() - 1]) {
+            return false;
+        }
+
+        let bitmap = internal.nybbles.index;
+        let mut keys = keys;
+
+        for (child_nybble, child) in (0..16)
+                .filter(|n| bitmap >> n & 1 == 1)
+                .zip(&internal.nybbles.data) {
+            let n = Some(child_nybble as u8);
+
+            // Any key before this child's run has a nybble with no child of its own.
+            if keys.partition_point(|key| try_nybble(internal.index, key) < n) > 0 {
+                return false;
+            }
+
+            let end = keys.partition_point(|key| try_nybble(internal.index, key) <= n);
+            if !child.contains_sorted(&keys[..end]) {
+                return false;
+            }
+            keys = &keys[end..];
+        }
+
+        keys.is_empty()
+    }
+
+
+    /// Clone this node, leaving out `subtrie`, the subtrie beneath it of keys starting with
+    /// `prefix`. Only the nodes on the path down to `subtrie` are rebuilt; every child off that
+    /// path is cloned whole.
+    fn clone_outside(&self, subtrie: &Node<K, V>, prefix: &[u8]) -> Option<Node<K, V>>
+        where K: Clone,
+              V: Clone
+    {
+        if ptr::eq(self, subtrie) {
+            return None;
+        }
+
+        let internal = match *self {
+            Node::Internal(ref internal) => internal,
+            Node::Leaf(..) => return Some(self.clone()),
+        };
+        let bitmap = internal.nybbles.index;
+        let on_path = nybble(internal.index, prefix) as usize;
+        let mut kept = Sparse::new();
+
+        for (child_nybble, child) in (0..16)
+                .filter(|n| bitmap >> n & 1 == 1)
+                .zip(&internal.nybbles.data) {
+            let child = if child_nybble == on_path {
+                child.clone_outside(subtrie, prefix)
+            } else {
+                Some(child.clone())
+            };
+
+            if let Some(child) = child {
+                kept.insert_fresh(child_nybble, child);
+            }
+        }
+
+        Node::from_remaining(internal.index, kept, 0)
+    }
+
+
+    /// Build an internal node from the children left after a removal, replacing it with its only
+    /// child if it has just one, or with nothing if it has none.
+    fn from_remaining(index: usize,
+                      mut nybbles: Sparse<Node<K, V>>,
+                      count: usize)
+                      -> Option<Node<K, V>> {
+        match nybbles.data.len() {
+            0 | 1 => nybbles.data.pop(),
+            _ => {
+                Some(Node::Internal(Internal {
+                                        index,
+                                        nybbles,
+                                        count,
+                                    }))
+            }
+        }
+    }
+
+
+    /// Replace this node, which must be internal, with its only child if it has just one. An
+    /// internal node must branch, so this restores the invariant after a child is removed.
+    fn collapse(&mut self) {
+        if self.mut_unwrap_internal().nybbles.data.len() == 1 {
+            let child = self.mut_unwrap_internal().nybbles.data.pop();
+            *self = match child {
+                Some(child) => child,
+                None => unsafe { debug_unreachable!() },
+            };
+        }
+    }
+
+
+    /// Collapse every internal node beneath this one, and this node itself, which has only one
+    /// child.
+    fn coalesce(&mut self) {
+        if let Node::Internal(ref mut internal) = *self {
+            for child in &mut internal.nybbles.data {
+                child.coalesce();
+            }
+        } else {
+            return;
+        }
+
+        self.collapse();
+    }
+
+
+    /// Get a mutable reference to the node which a key first differing from the trie at nybble
+    /// `index` must be inserted beside: the first node along the key's path which is either a leaf
+    /// or branches at or after `index`.
+    fn get_insertion_point_mut<L: AsRef<[u8]>>(&mut self, key: L, index: usize) -> &mut Node<K, V> {
+        let descend = match *self {
+            Node::Internal(ref internal) => internal.index < index,
+            Node::Leaf(..) => false,
+        };
+
+        if descend {
+            let internal = self.mut_unwrap_internal();
+            let search_nybble = nybble(internal.index, &key);
+
+            // The key agrees with every key in this subtrie up to `index`, so it must have a
+            // child to follow.
+            match internal.nybbles.get_mut(search_nybble as usize) {
+                Some(node) => node.get_insertion_point_mut(key, index),
+                None => unsafe { debug_unreachable!() },
+            }
+        } else {
+            self
+        }
+    }
+
+
+    /// Insert a leaf whose key first differs from every key beneath this node at nybble `index`.
+    fn insert_fresh_leaf_at(&mut self, leaf: Leaf<K, V>, index: usize) {
+        let closest = self.get_insertion_point_mut(&leaf.key, index);
+
+        let branches_here = match *closest {
+            Node::Internal(ref internal) => internal.index == index,
+            Node::Leaf(..) => false,
+        };
+
+        if branches_here {
+            // We can do a "fresh" insert here (that is, safely assume there is no value sharing
+            // the same nybble already in this internal node) because the key first differs from
+            // the trie at this node's index.
+            closest.mut_unwrap_internal().insert_fresh_leaf(leaf);
+        } else {
+            // Otherwise, `closest` is either a leaf or an internal node branching after `index`.
+            // We `mem::replace` `closest` with a fresh internal node branching at `index`, and then
+            // insert both the new leaf and the displaced node beneath it.
+            let displaced_nybble = nybble(index, &closest.get_closest_leaf(&leaf.key).key);
+            let displaced = mem::replace(closest, Node::Internal(Internal::new(index)));
+            let internal = closest.mut_unwrap_internal();
+
+            internal.nybbles.insert_fresh(displaced_nybble as usize, displaced);
+            internal.insert_fresh_leaf(leaf);
+        }
+    }
+
+
+    fn get_first_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                Node::Internal(ref internal) => node = &internal.nybbles.data[0],
+            }
+        }
+    }
+
+
+    fn get_last_leaf(&self) -> &Leaf<K, V> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return leaf,
+                Node::Internal(ref internal) => {
+                    node = match internal.nybbles.data.last() {
+                        Some(child) => child,
+                        None => unsafe { debug_unreachable!() },
+                    };
+                }
+            }
+        }
+    }
+
+
+    /// Get references to the subtries whose last and first leaves hold the greatest key less than
+    /// the given key and the least key greater than it.
+    #[allow(clippy::type_complexity)]
+    fn get_adjacent<L: AsRef<[u8]>>(&self, key: L) -> (Option<&Node<K, V>>, Option<&Node<K, V>>) {
+        let key = key.as_ref();
+
+        // Every key in the trie agrees with the closest leaf up to the first nybble where the
+        // closest leaf differs from `key`, so that is where the rest of the trie branches away
+        // from `key`. A key which runs out of nybbles there sorts before any which continue.
+        let mismatch = nybble_mismatch(self.get_closest_leaf(key).key.as_ref(), key);
+        let key_nybble = mismatch.and_then(|index| try_nybble(index, key));
+
+        let mut node = self;
+        let mut before = None;
+        let mut after = None;
+
+        loop {
+            match *node {
+                Node::Internal(ref internal) if mismatch.is_none_or(|m| internal.index < m) => {
+                    let nybbles = &internal.nybbles;
+                    let actual = nybbles.actual(nybble(internal.index, key) as usize);
+
+                    before = nybbles.data[..actual].last().or(before);
+                    after = nybbles.data.get(actual + 1).or(after);
+                    node = &nybbles.data[actual];
+                }
+                Node::Internal(ref internal) if mismatch == Some(internal.index) => {
+                    let nybbles = &internal.nybbles;
+                    let actual = key_nybble.map_or(0, |n| nybbles.actual(n as usize));
+
+                    before = nybbles.data[..actual].last().or(before);
+                    after = nybbles.data.get(actual).or(after);
+                    break;
+                }
+                _ => {
+                    // Below the mismatch, every key in this subtrie compares to `key` the same
+                    // way. With no mismatch, this is the leaf holding `key` itself.
+                    if let Some(index) = mismatch {
+                        if try_nybble(index, &node.get_first_leaf().key) < key_nybble {
+                            before = Some(node);
+                        } else {
+                            after = Some(node);
+                        }
+                    }
+                    break;
+                }
+            }
+        }
+
+        (before, after)
+    }
+
+
+    /// Get a reference to the root of the subtrie containing exactly the keys which start with the
+    /// given prefix, if any do.
+    fn get_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> Option<&Node<K, V>> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Internal(ref internal) if internal.index < 2 * prefix.as_ref().len() => {
+                    node = internal
+                        .nybbles
+                        .get(nybble(internal.index, &prefix) as usize)?;
+                }
+                _ => break,
+            }
+        }
+
+        // Every key beneath `node` agrees with `prefix` on the nybbles we have followed, but not
+        // necessarily on the ones in between, so we must check one of them in full.
+        if node.get_closest_leaf(&prefix)
+               .key
+               .as_ref()
+               .starts_with(prefix.as_ref()) {
+            Some(node)
+        } else {
+            None
+        }
+    }
+
+
+    fn total_key_bytes(&self) -> usize {
+        match *self {
+            Node::Leaf(ref leaf) => leaf.key.as_ref().len(),
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(Node::total_key_bytes)
+                    .sum()
+            }
+        }
+    }
+
+
+    fn get_prefix_mut<L: AsRef<[u8]>>(&mut self, prefix: L) -> Option<&mut Node<K, V>> {
+        let descend = match *self {
+            Node::Internal(ref internal) => internal.index < 2 * prefix.as_ref().len(),
+            Node::Leaf(..) => false,
+        };
+
+        if descend {
+            let internal = self.mut_unwrap_internal();
+
+            internal
+                .nybbles
+                .get_mut(nybble(internal.index, &prefix) as usize)?
+                .get_prefix_mut(prefix)
+        } else if self.get_closest_leaf(&prefix)
+                      .key
+                      .as_ref()
+                      .starts_with(prefix.as_ref()) {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+
+    fn count_distinct_prefixes(&self, byte_depth: usize) -> usize {
+        match *self {
+            Node::Leaf(ref leaf) => (leaf.key.as_ref().len() >= byte_depth) as usize,
+
+            // All keys beneath an internal node share their nybbles before its index, and are
+            // longer than it.
+            Node::Internal(ref internal) if internal.index >= 2 * byte_depth => 1,
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(|child| child.count_distinct_prefixes(byte_depth))
+                    .sum()
+            }
+        }
+    }
+
+
+    fn walk_internal_nodes<F: FnMut(usize, &[u8], u8)>(&self, depth: usize, f: &mut F) {
+        if let Node::Internal(ref internal) = *self {
+            // Every key beneath an internal node shares its bytes before the node's index.
+            let prefix = &self.get_first_leaf().key.as_ref()[..internal.index >> 1];
+            f(depth, prefix, internal.nybbles.data.len() as u8);
+
+            for child in &internal.nybbles.data {
+                child.walk_internal_nodes(depth + 1, f);
+            }
+        }
+    }
+
+
+    fn count_reachable_nybbles(&self, depth: usize, masks: &mut Vec<u32>) {
+        if let Node::Internal(ref internal) = *self {
+            if masks.len() <= depth {
+                masks.push(0);
+            }
+            masks[depth] |= internal.nybbles.index;
+
+            for child in &internal.nybbles.data {
+                child.count_reachable_nybbles(depth + 1, masks);
+            }
+        }
+    }
+
+
+    /// Find the greatest nybble index at which both this node and `other` have an internal node
+    /// for the same prefix, walking the two side by side.
+    fn max_shared_index(&self, other: &Node<K, V>) -> Option<usize> {
+        let (left, right) = match (self, other) {
+            (Node::Internal(left), Node::Internal(right)) => (left, right),
+            _ => return None,
+        };
+
+        match left.index.cmp(&right.index) {
+            // Every key beneath the deeper node agrees on the nybble the shallower one branches
+            // on, so only one child of the shallower node can meet it.
+            Ordering::Less => {
+                let child = left.nybbles
+                    .get(nybble(left.index, &other.get_first_leaf().key) as usize)?;
+                child.max_shared_index(other)
+            }
+            Ordering::Greater => other.max_shared_index(self),
+            Ordering::Equal => {
+                let mismatch = nybble_mismatch(self.get_first_leaf().key.as_ref(),
+                                               other.get_first_leaf().key.as_ref());
+                if mismatch.is_some_and(|mismatch| mismatch < left.index) {
+                    return None;
+                }
+
+                (0..16)
+                    .filter_map(|n| match (left.nybbles.get(n), right.nybbles.get(n)) {
+                                    (Some(l), Some(r)) => l.max_shared_index(r),
+                                    _ => None,
+                                })
+                    .max()
+                    .map_or(Some(left.index), |deepest| Some(cmp::max(deepest, left.index)))
+            }
+        }
+    }
+
+
+    /// The whole bytes shared by every key beneath this node: the entire key for a leaf.
+    fn whole_byte_prefix(&self) -> &[u8] {
+        match *self {
+            Node::Leaf(ref leaf) => leaf.key.as_ref(),
+            Node::Internal(ref internal) => {
+                &self.get_first_leaf().key.as_ref()[..internal.index >> 1]
+            }
+        }
+    }
+
+
+    fn export_edges(&self, edges: &mut Vec<(Vec<u8>, Vec<u8>, u8)>) {
+        if let Node::Internal(ref internal) = *self {
+            let prefix = self.whole_byte_prefix();
+
+            for child in &internal.nybbles.data {
+                let child_nybble = nybble(internal.index, &child.get_first_leaf().key);
+                edges.push((prefix.to_vec(), child.whole_byte_prefix().to_vec(), child_nybble));
+                child.export_edges(edges);
+            }
+        }
+    }
+
+
+    fn count_nodes_at_depth(&self, depth: usize) -> (usize, usize) {
+        match *self {
+            Node::Leaf(..) if depth == 0 => (0, 1),
+            Node::Internal(..) if depth == 0 => (1, 0),
+            Node::Leaf(..) => (0, 0),
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(|child| child.count_nodes_at_depth(depth - 1))
+                    .fold((0, 0), |(i, l), (ci, cl)| (i + ci, l + cl))
+            }
+        }
+    }
+
+
+    /// Count the internal nodes beneath and including this one, and the edges leaving them.
+    fn count_internal(&self) -> (usize, usize) {
+        match *self {
+            Node::Leaf(..) => (0, 0),
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(Node::count_internal)
+                    .fold((1, internal.nybbles.data.len()),
+                          |(n, e), (cn, ce)| (n + cn, e + ce))
+            }
+        }
+    }
+
+
+    fn capacity(&self) -> usize {
+        match *self {
+            Node::Leaf(..) => 0,
+            Node::Internal(ref internal) => {
+                internal.nybbles.data.capacity() +
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(Node::capacity)
+                    .sum::<usize>()
+            }
+        }
+    }
+
+
+    /// Reserve child slots for `additional` more branches in every internal node beneath and
+    /// including this one, up to the sixteen any node can hold.
+    fn reserve(&mut self, additional: usize) {
+        if let Node::Internal(ref mut internal) = *self {
+            let data = &mut internal.nybbles.data;
+            data.reserve(cmp::min(additional, 16 - data.len()));
+
+            for child in data {
+                child.reserve(additional);
+            }
+        }
+    }
+
+
+    /// The greatest number of internal nodes on a path from this node down to a leaf.
+    fn height(&self) -> usize {
+        match *self {
+            Node::Leaf(..) => 0,
+            Node::Internal(ref internal) => {
+                1 +
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(Node::height)
+                    .max()
+                    .unwrap_or(0)
+            }
+        }
+    }
+
+
+    /// Count the leaves beneath this node by walking every one of them.
+    fn count_leaves(&self) -> usize {
+        match *self {
+            Node::Leaf(..) => 1,
+            Node::Internal(ref internal) => {
+                internal
+                    .nybbles
+                    .data
+                    .iter()
+                    .map(Node::count_leaves)
+                    .sum()
+            }
+        }
+    }
+
+
+    /// Find the `n`th leaf beneath this node in key order, skipping whole subtries by their cached
+    /// `count`, which must be up to date.
+    fn get_nth_leaf_counted(&self, mut n: usize) -> Option<&Leaf<K, V>> {
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Leaf(ref leaf) => return if n == 0 { Some(leaf) } else { None },
+                Node::Internal(ref internal) => {
+                    node = internal
+                        .nybbles
+                        .data
+                        .iter()
+                        .find(|child| {
+                            let count = match **child {
+                                Node::Leaf(..) => 1,
+                                Node::Internal(ref internal) => internal.count,
+                            };
+
+                            if n < count {
+                                true
+                            } else {
+                                n -= count;
+                                false
+                            }
+                        })?;
+                }
+            }
+        }
+    }
+
+
+    /// Count the leaves beneath this node whose keys are less than `key`. The subtries passed over
+    /// on the way down to `key` are sized by their cached `count` if `counted`, and by counting
+    /// their leaves otherwise.
+    fn count_leaves_before(&self, key: &[u8], counted: bool) -> usize {
+        let size = |node: &Node<K, V>| match *node {
+            Node::Internal(ref internal) if counted => internal.count,
+            _ => node.count_leaves(),
+        };
+
+        // Follow `key` down to where it leaves the trie. If that is at a node's index, its
+        // children before `key`'s nybble there come before it; otherwise every key in the subtrie
+        // reached compares with it as the closest leaf's does.
+        let closest = self.get_closest_leaf(key).key.as_ref();
+        let mismatch = nybble_mismatch(closest, key);
+        let mut before = 0;
+        let mut node = self;
+
+        loop {
+            match *node {
+                Node::Internal(ref internal) if mismatch.is_none_or(|m| internal.index <= m) => {
+                    let at = try_nybble(internal.index, key)
+                        .map_or(0, |nybble| internal.nybbles.actual(nybble as usize));
+                    before += internal.nybbles.data[..at].iter().map(size).sum::<usize>();
+
+                    if mismatch == Some(internal.index) {
+                        return before;
+                    }
+                    node = &internal.nybbles.data[at];
+                }
+                _ => return if closest < key { before + size(node) } else { before },
+            }
+        }
+    }
+
+
+    /// Store the number of leaves beneath each internal node in its `count`, returning the number
+    /// of leaves beneath this node.
+    fn build_counts(&mut self) -> usize {
+        match *self {
+            Node::Leaf(..) => 1,
+            Node::Internal(ref mut internal) => {
+                internal.count = internal
+                    .nybbles
+                    .data
+                    .iter_mut()
+                    .map(Node::build_counts)
+                    .sum();
+                internal.count
+            }
+        }
+    }
+}
+
+
+pub struct Trie<K, V> {
+    root: Option<Node<K, V>>,
+    len: usize,
+    // Whether the `count` of every internal node is up to date.
+    counts_valid: bool,
+}
+
+
+impl<K: AsRef<[u8]> + AsMut<[u8]> + Copy, V> Trie<K, V> {
+    pub fn new() -> Trie<K, V> {
+        Trie::from_root(None)
+    }
+
+
+    fn from_root(root: Option<Node<K, V>>) -> Trie<K, V> {
+        Trie {
+            len: root.as_ref().map_or(0, Node::count_leaves),
+            root,
+            counts_valid: false,
+        }
+    }
+
+
+    /// The number of entries in the trie.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+
+    /// Returns `true` if the trie holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+
+    /// Iterate over the entries in key order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter::new(self.root.as_ref(), self.len)
+    }
+
+
+    /// Build a trie by calling `f` with the indices `0, 1, 2, ...` and inserting each entry it
+    /// returns, until it returns `None`.
+    pub fn from_fn<F: FnMut(usize) -> Option<(K, V)>>(mut f: F) -> Trie<K, V> {
+        let mut trie = Trie::new();
+
+        for i in 0.. {
+            match f(i) {
+                Some((key, val)) => trie.insert(key, val),
+                None => break,
+            };
+        }
+
+        trie
+    }
+
+
+    pub fn insert<L: AsRef<[u8]>>(&mut self, key: L, val: V) -> Option<V> {
+        // Build the leaf up front, so that a key of the wrong length panics before the trie is
+        // modified.
+        let leaf: Leaf<K, V> = Leaf::new(key, val);
+
+        match self.root {
+            Some(ref mut root) => {
+                let index = match nybble_mismatch(root.get_closest_leaf(leaf.key).key.as_ref(),
+                                                  leaf.key.as_ref()) {
+                    Some(index) => index,
+
+                    // If the closest leaf has exactly our key, we can replace the leaf value and
+                    // return the displaced value.
+                    None => {
+                        return root.get_leaf_mut(leaf.key)
+                                   .map(|existing| mem::replace(&mut existing.val, leaf.val));
+                    }
+                };
+
+                root.insert_fresh_leaf_at(leaf, index);
+                self.len += 1;
+                self.invalidate_prefix_count_cache();
+                None
+            }
+
+            // If the root is empty, simply insert a singleton leaf.
+            ref mut none => {
+                *none = Some(Node::Leaf(leaf));
+                self.len += 1;
+                None
+            }
+        }
+    }
+
+
+    /// Insert an entry as with `insert`, also returning the position of its key in key order,
+    /// counting from zero. The position is found on the way down to the key: while
+    /// `build_prefix_count_cache` is in effect, the entries passed over are read from the cache, so
+    /// this takes time proportional to the height of the trie; otherwise they are counted.
+    pub fn insert_returning_position<L: AsRef<[u8]>>(&mut self,
+                                                     key: L,
+                                                     val: V)
+                                                     -> (Option<V>, usize) {
+        let position = self.root
+            .as_ref()
+            .map_or(0, |root| root.count_leaves_before(key.as_ref(), self.counts_valid));
+
+        (self.insert(key, val), position)
+    }
+
+
+    /// Insert the entry only if the trie holds fewer than `max_entries` entries, returning whether
+    /// it was inserted. If it is, any existing value for `key` is replaced as with `insert`.
+    pub fn saturating_insert<L: AsRef<[u8]>>(&mut self,
+                                             key: L,
+                                             val: V,
+                                             max_entries: usize)
+                                             -> bool {
+        if self.len < max_entries {
+            self.insert(key, val);
+            true
+        } else {
+            false
+        }
+    }
+
+
+    fn get_leaf<L: AsRef<[u8]>>(&self, key: L) -> Option<&Leaf<K, V>> {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_leaf_on_path(&key))
+            .filter(|leaf| leaf.key.as_ref() == key.as_ref())
+    }
+
+
+    fn get_leaf_mut<L: AsRef<[u8]>>(&mut self, key: L) -> Option<&mut Leaf<K, V>> {
+        self.root.as_mut().and_then(|root| root.get_leaf_mut(key))
+    }
+
+
+    /// Get mutable references to the values stored for two distinct keys at once, or `None` if
+    /// either is absent.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the two keys are equal.
+    pub fn get_two_mut<L1, L2>(&mut self, key1: L1, key2: L2) -> Option<(&mut V, &mut V)>
+        where L1: AsRef<[u8]>,
+              L2: AsRef<[u8]>
+    {
+        assert!(key1.as_ref() != key2.as_ref(),
+                "cannot borrow the value for key {:?} mutably twice",
+                key1.as_ref());
+
+        self.root
+            .as_mut()
+            .and_then(|root| root.get_two_vals_mut(key1, key2))
+    }
+
+
+    fn remove_leaf<L: AsRef<[u8]>>(&mut self, key: L) -> Option<Leaf<K, V>> {
+        let root_matches = match self.root {
+            Some(Node::Leaf(ref leaf)) => leaf.key.as_ref() == key.as_ref(),
+            Some(Node::Internal(..)) => false,
+            None => return None,
+        };
+
+        if root_matches {
+            self.len -= 1;
+            self.root.take().map(Node::unwrap_leaf)
+        } else {
+            let removed = self.root
+                .as_mut()
+                .and_then(|root| match *root {
+                              Node::Internal(..) => root.remove_child_leaf(key),
+                              Node::Leaf(..) => None,
+                          });
+
+            if removed.is_some() {
+                self.len -= 1;
+                self.invalidate_prefix_count_cache();
+            }
+
+            removed
+        }
+    }
+
+
+    /// Trace the lookup of `key` through the trie, returning for each internal node visited its
+    /// nybble index, the nybble of `key` at that index, and whether the node has a child for that
+    /// nybble. The trace ends at a leaf, at a missing child, or where `key` runs out of nybbles.
+    pub fn path_to_key<L: AsRef<[u8]>>(&self, key: L) -> Vec<(usize, u8, bool)> {
+        let mut path = Vec::new();
+        let mut node = self.root.as_ref();
+
+        while let Some(Node::Internal(internal)) = node {
+            let search_nybble = match try_nybble(internal.index, &key) {
+                Some(search_nybble) => search_nybble,
+                None => break,
+            };
+
+            node = internal.nybbles.get(search_nybble as usize);
+            path.push((internal.index, search_nybble, node.is_some()));
+        }
+
+        path
+    }
+
+
+    /// Get the entry for a key given as raw bytes, for in-place manipulation.
+    pub fn entry_for_bytes(&mut self, key: &[u8]) -> Entry<'_, K, V> {
+        if self.get_leaf(key).is_some() {
+            match self.get_leaf_mut(key) {
+                Some(leaf) => Entry::Occupied(OccupiedEntry { leaf }),
+                None => unsafe { debug_unreachable!() },
+            }
+        } else {
+            Entry::Vacant(VacantEntry {
+                              trie: self,
+                              key: key.to_vec(),
+                          })
+        }
+    }
+
+
+    /// Get the value stored for `key`, without checking that the leaf found holds `key`.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be stored in the trie, for example as checked by a lookup since which the trie
+    /// has not been modified. If the key's path ends before reaching a leaf, the behavior is
+    /// undefined; otherwise the value returned may belong to another key.
+    pub unsafe fn get_unchecked<L: AsRef<[u8]>>(&self, key: L) -> &V {
+        match self.root.as_ref().and_then(|root| root.get_leaf_on_path(key)) {
+            Some(leaf) => &leaf.val,
+            None => debug_unreachable!(),
+        }
+    }
+
+
+    /// Get the value stored for `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the bytes of the key if it is not present.
+    pub fn get_or_panic<L: AsRef<[u8]>>(&self, key: L) -> &V {
+        match self.get_leaf(&key) {
+            Some(leaf) => &leaf.val,
+            None => panic!("key {:?} not found in trie", key.as_ref()),
+        }
+    }
+
+
+    /// Get the value stored for `key`, or a default value if there is none. The trie is not
+    /// modified.
+    pub fn get_or_default<L: AsRef<[u8]>>(&self, key: L) -> Cow<'_, V>
+        where V: Clone + Default
+    {
+        match self.get_leaf(key) {
+            Some(leaf) => Cow::Borrowed(&leaf.val),
+            None => Cow::Owned(V::default()),
+        }
+    }
+
+
+    /// Get the value stored for `key`, first inserting `V::default()` if there is none.
+    pub fn default_value_for<L: AsRef<[u8]>>(&mut self, key: L) -> &V
+        where V: Default
+    {
+        self.entry_for_bytes(key.as_ref()).or_insert_with(V::default)
+    }
+
+
+    /// Get the value stored for `key`, first computing and inserting it with `f` if there is none.
+    /// If `f` fails, its error is returned and the trie is left unchanged.
+    pub fn get_or_compute<L, E, F>(&mut self, key: L, f: F) -> Result<&V, E>
+        where L: AsRef<[u8]>,
+              F: FnOnce(&[u8]) -> Result<V, E>
+    {
+        match self.entry_for_bytes(key.as_ref()) {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let val = f(key.as_ref())?;
+                Ok(entry.insert(val))
+            }
+        }
+    }
+
+
+    /// Remove every entry whose key does not start with `prefix`.
+    pub fn retain_prefix<L: AsRef<[u8]>>(&mut self, prefix: L) {
+        // The retained entries form a single subtrie, which simply becomes the new root.
+        self.root = self.root
+            .as_mut()
+            .and_then(|root| root.get_prefix_mut(prefix))
+            .map(|subtrie| mem::replace(subtrie, Node::Internal(Internal::new(0))));
+        self.len = self.root.as_ref().map_or(0, Node::count_leaves);
+    }
+
+
+    /// Detach the subtrie of entries whose keys start with `prefix`, if there are any.
+    fn remove_prefix_subtrie<L: AsRef<[u8]>>(&mut self, prefix: L) -> Option<Node<K, V>> {
+        let at_root = match self.root {
+            Some(Node::Internal(ref internal)) => internal.index >= 2 * prefix.as_ref().len(),
+            Some(Node::Leaf(..)) => true,
+            None => return None,
+        };
+
+        if at_root {
+            let matches = self.root
+                .as_ref()
+                .and_then(|root| root.get_prefix(&prefix))
+                .is_some();
+
+            if matches {
+                self.len = 0;
+                self.root.take()
+            } else {
+                None
+            }
+        } else {
+            let removed = self.root
+                .as_mut()
+                .and_then(|root| root.remove_child_prefix(prefix));
+
+            if let Some(ref subtrie) = removed {
+                self.len -= subtrie.count_leaves();
+                self.invalidate_prefix_count_cache();
+            }
+
+            removed
+        }
+    }
+
+
+    /// Call `f` with a view of the entries whose keys start with `prefix`, or return `None` without
+    /// calling it if there are no such entries. The view borrows the subtrie in place; it is a
+    /// `Subtrie` rather than a `Trie`, since a trie must own its nodes.
+    pub fn with_prefix_scope<L, R, F>(&self, prefix: L, f: F) -> Option<R>
+        where L: AsRef<[u8]>,
+              F: FnOnce(Subtrie<'_, K, V>) -> R
+    {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_prefix(prefix))
+            .map(|root| f(Subtrie { root }))
+    }
+
+
+    /// Remove every entry whose key starts with `prefix`, passing ownership of each to `f` in key
+    /// order.
+    pub fn consume_matching<L, F>(&mut self, prefix: L, mut f: F)
+        where L: AsRef<[u8]>,
+              F: FnMut(K, V)
+    {
+        if let Some(subtrie) = self.remove_prefix_subtrie(prefix) {
+            subtrie.into_each_leaf(&mut |leaf| f(leaf.key, leaf.val));
+        }
+    }
+
+
+    /// Move the entries whose keys start with `split_prefix` out into a new trie, leaving the rest.
+    pub fn partition_in_place(&mut self, split_prefix: &[u8]) -> Trie<K, V> {
+        Trie::from_root(self.remove_prefix_subtrie(split_prefix))
+    }
+
+
+    /// Detach the subtrie of entries whose keys start with `prefix` as a trie of its own, or return
+    /// `None` if there are no such entries.
+    pub fn take_prefix<L: AsRef<[u8]>>(&mut self, prefix: L) -> Option<Trie<K, V>> {
+        self.remove_prefix_subtrie(prefix)
+            .map(|subtrie| Trie::from_root(Some(subtrie)))
+    }
+
+
+    /// Rename every key starting with `old_prefix` to start with `new_prefix` instead, returning
+    /// the number of entries renamed. Renamed entries replace any existing entries with the same
+    /// keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefixes differ in length, as the renamed keys would no longer fit in a `K`.
+    pub fn flip_prefix(&mut self, old_prefix: &[u8], new_prefix: &[u8]) -> usize {
+        assert_eq!(old_prefix.len(),
+                   new_prefix.len(),
+                   "cannot rename a key prefix to one of a different length");
+
+        let mut renamed = Vec::new();
+        if let Some(subtrie) = self.remove_prefix_subtrie(old_prefix) {
+            subtrie.into_each_leaf(&mut |mut leaf| {
+                                       leaf.key.as_mut()[..new_prefix.len()]
+                                           .copy_from_slice(new_prefix);
+                                       renamed.push(leaf);
+                                   });
+        }
+
+        let count = renamed.len();
+        for leaf in renamed {
+            self.insert(leaf.key, leaf.val);
+        }
+
+        count
+    }
+
+
+    /// Move every entry under `src` to the same key with `src` replaced by `dst`, returning the
+    /// number of entries moved. This is another name for `flip_prefix`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefixes differ in length.
+    pub fn move_prefix(&mut self, src: &[u8], dst: &[u8]) -> usize {
+        self.flip_prefix(src, dst)
+    }
+
+
+    /// Look up each of a fixed number of keys, substituting `default` for any which are absent.
+    pub fn get_many_or_default<'a, const N: usize>(&'a self,
+                                                   keys: [&[u8]; N],
+                                                   default: &'a V)
+                                                   -> [&'a V; N] {
+        keys.map(|key| self.get_leaf(key).map_or(default, |leaf| &leaf.val))
+    }
+
+
+    /// Look up each of a fixed number of keys. The trie is descended once to the subtrie of the
+    /// bytes all the keys share, and each lookup continues from there.
+    pub fn multi_get<'a, const N: usize>(&'a self, keys: [&[u8]; N]) -> [Option<&'a V>; N] {
+        let shared = keys.iter().fold(keys.first().map_or(&[][..], |key| *key), |shared, key| {
+            let shared_len = shared.iter().zip(key.iter()).take_while(|&(a, b)| a == b).count();
+            &shared[..shared_len]
+        });
+
+        // If no key starts with the shared bytes, none of the keys can be present.
+        let subtrie = self.root.as_ref().and_then(|root| root.get_prefix(shared));
+        keys.map(|key| {
+            subtrie
+                .and_then(|subtrie| subtrie.get_leaf_on_path(key))
+                .filter(|leaf| leaf.key.as_ref() == key)
+                .map(|leaf| &leaf.val)
+        })
+    }
+
+
+    /// Remove the given key from the trie, returning its value, or `Err(KeyNotFound)` if it was not
+    /// present.
+    pub fn try_remove<L: AsRef<[u8]>>(&mut self, key: L) -> Result<V, KeyNotFound> {
+        self.remove_leaf(key).map(|leaf| leaf.val).ok_or(KeyNotFound)
+    }
+
+
+    /// Remove the given key from the trie, returning ownership of both the stored key and its
+    /// value, or `None` if it was not present.
+    pub fn take<L: AsRef<[u8]>>(&mut self, key: L) -> Option<(K, V)> {
+        self.remove_leaf(key).map(|leaf| (leaf.key, leaf.val))
+    }
+
+
+    /// Apply `f` to the value stored under `key`, if any, keeping the value it returns or removing
+    /// the entry if it returns `None`. Returns `true` if the key was present. A kept value is
+    /// written back into the entry's leaf in place, leaving the structure of the trie unchanged.
+    /// If `f` panics, the entry is removed and its value is lost.
+    pub fn update_or_remove<L, F>(&mut self, key: L, f: F) -> bool
+        where L: AsRef<[u8]>,
+              F: FnOnce(V) -> Option<V>
+    {
+        // Removes the entry whose value was moved out to `f` without dropping that value again,
+        // unless it is forgotten once a new value has been written back.
+        struct Discard<'a, K: 'a + AsRef<[u8]> + AsMut<[u8]> + Copy, V: 'a, L: AsRef<[u8]>> {
+            trie: &'a mut Trie<K, V>,
+            key: L,
+        }
+
+        impl<'a, K: AsRef<[u8]> + AsMut<[u8]> + Copy, V, L: AsRef<[u8]>> Drop
+            for Discard<'a, K, V, L> {
+            fn drop(&mut self) {
+                if let Some(leaf) = self.trie.remove_leaf(&self.key) {
+                    mem::forget(leaf);
+                }
+            }
+        }
+
+        let val = match self.get_leaf_mut(&key) {
+            Some(leaf) => unsafe { ptr::read(&leaf.val) },
+            None => return false,
+        };
+
+        let discard = Discard { trie: self, key };
+        if let Some(val) = f(val) {
+            if let Some(leaf) = discard.trie.get_leaf_mut(&discard.key) {
+                unsafe {
+                    ptr::write(&mut leaf.val, val);
+                }
+            }
+            mem::forget(discard);
+        }
+
+        true
+    }
+
+
+    /// Remove each of the given keys from the trie, returning the number of keys which were
+    /// present. The keys are sorted first, so that all of them are removed in a single walk of the
+    /// trie, sharing the descent through the prefixes they have in common.
+    pub fn remove_bulk<I>(&mut self, keys: I) -> usize
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>
+    {
+        let mut keys = keys.into_iter().collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+
+        let mut removed = 0;
+        if let Some(root) = self.root.take() {
+            self.root = root.remove_sorted(&keys, &mut removed);
+        }
+
+        if removed > 0 {
+            self.len -= removed;
+            self.invalidate_prefix_count_cache();
+        }
+
+        removed
+    }
+
+
+    /// Build a trie from a map keyed by byte vectors. The map is already in key order, so the trie
+    /// is built with `insert_batch_sorted`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any key differs in length from a `K`.
+    pub fn from_prefix_map(map: BTreeMap<Vec<u8>, V>) -> Trie<K, V> {
+        Trie::new().insert_batch_sorted(map.into_iter()
+                                            .map(|(key, val)| (key_from_bytes(key), val)))
+    }
+
+
+    /// Detach the nodes on the path from the root to the greatest key, returning them in order
+    /// from the root down. Each internal node is missing its last child, which follows it.
+    fn take_rightmost_path(&mut self) -> Vec<Node<K, V>> {
+        let mut path = Vec::new();
+        let mut next = self.root.take();
+
+        while let Some(mut node) = next {
+            next = match node {
+                Node::Internal(ref mut internal) => internal.nybbles.pop(),
+                Node::Leaf(..) => None,
+            };
+            path.push(node);
+        }
+
+        path
+    }
+
+
+    /// Reattach a path detached by `take_rightmost_path`, making it the trie's root again.
+    fn restore_rightmost_path(&mut self, mut path: Vec<Node<K, V>>) {
+        let mut root = path.pop();
+
+        // Every node on the path holds the greatest key.
+        if let Some(Node::Leaf(ref leaf)) = root {
+            let last = leaf.key;
+
+            while let Some(mut parent) = path.pop() {
+                if let Some(child) = root {
+                    parent.mut_unwrap_internal().insert_fresh_child(&last, child);
+                }
+                root = Some(parent);
+            }
+        }
+
+        self.root = root;
+    }
+
+
+    /// Insert entries given in lexicographic order of their keys, returning the resulting trie.
+    ///
+    /// While each key is greater than every key already in the trie, it belongs on the path to
+    /// the greatest key, beside the deepest node on that path which branches no later than where
+    /// the two keys differ. That path is kept detached from the trie while inserting, so each key
+    /// is placed by climbing back up it rather than descending from the root. Entries out of order
+    /// are still inserted correctly, just without that saving.
+    pub fn insert_batch_sorted<I: IntoIterator<Item = (K, V)>>(mut self, iter: I) -> Self {
+        let len = self.len;
+        let mut path = self.take_rightmost_path();
+
+        for (key, val) in iter {
+            let (last, mut child) = match path.pop() {
+                Some(Node::Leaf(leaf)) if key.as_ref() > leaf.key.as_ref() => {
+                    (leaf.key, Node::Leaf(leaf))
+                }
+                Some(last) => {
+                    path.push(last);
+                    self.restore_rightmost_path(path);
+                    self.insert(key, val);
+                    path = self.take_rightmost_path();
+                    continue;
+                }
+                None => {
+                    path.push(Node::Leaf(Leaf { key, val }));
+                    self.len += 1;
+                    continue;
+                }
+            };
+
+            let index = match nybble_mismatch(last.as_ref(), key.as_ref()) {
+                Some(index) => index,
+                None => unsafe { debug_unreachable!() },
+            };
+
+            // Close off every node on the path branching after `index`, as no later key can fall
+            // beneath them, then hang what remains of the path beside the new key.
+            loop {
+                match path.last_mut() {
+                    Some(Node::Internal(parent)) if parent.index > index => {}
+                    Some(Node::Internal(parent)) if parent.index == index => {
+                        parent.insert_fresh_child(&last, child);
+                        break;
+                    }
+                    _ => {
+                        let mut internal = Internal::new(index);
+                        internal.insert_fresh_child(&last, child);
+                        path.push(Node::Internal(internal));
+                        break;
+                    }
+                }
+
+                let mut parent = match path.pop() {
+                    Some(parent) => parent,
+                    None => unsafe { debug_unreachable!() },
+                };
+                parent.mut_unwrap_internal().insert_fresh_child(&last, child);
+                child = parent;
+            }
+
+            path.push(Node::Leaf(Leaf { key, val }));
+            self.len += 1;
+        }
+
+        self.restore_rightmost_path(path);
+        if self.len != len {
+            self.invalidate_prefix_count_cache();
+        }
+
+        self
+    }
+
+
+    /// Insert each of a fixed number of key-value pairs.
+    pub fn insert_many<const N: usize, L: AsRef<[u8]>>(&mut self, pairs: [(L, V); N]) {
+        for (key, val) in pairs {
+            self.insert(key, val);
+        }
+    }
+
+
+    /// Insert a clone of each key-value pair in a slice.
+    pub fn extend_from_slice<L: AsRef<[u8]>>(&mut self, pairs: &[(L, V)])
+        where V: Clone
+    {
+        for (key, val) in pairs {
+            self.insert(key, val.clone());
+        }
+    }
+
+
+    /// Insert a clone of each entry of `other`, replacing the values of keys already present.
+    pub fn extend_from_trie(&mut self, other: &Trie<K, V>)
+        where V: Clone
+    {
+        for (key, val) in other.iter() {
+            self.insert(key, val.clone());
+        }
+    }
+
+
+    /// Insert a clone of each entry of `other` whose key starts with `prefix`, replacing the values
+    /// of keys already present.
+    pub fn merge_prefix<L: AsRef<[u8]>>(&mut self, other: &Trie<K, V>, prefix: L)
+        where V: Clone
+    {
+        if let Some(subtrie) = other.root.as_ref().and_then(|root| root.get_prefix(prefix)) {
+            subtrie.for_each_leaf(&mut |leaf| {
+                                      self.insert(leaf.key, leaf.val.clone());
+                                  });
+        }
+    }
+
+
+    /// Move every entry of `other` into this trie, except those whose keys are already present
+    /// here, which keep their existing values.
+    pub fn union_into(&mut self, other: Trie<K, V>) {
+        if let Some(root) = other.root {
+            root.into_each_leaf(&mut |leaf| if self.get_leaf(leaf.key).is_none() {
+                                      self.insert(leaf.key, leaf.val);
+                                  });
+        }
+    }
+
+
+    /// Remove every entry whose key is also present in `other`. Both tries are walked together,
+    /// so subtries of either which share no prefix with the other are skipped whole.
+    pub fn difference_in_place(&mut self, other: &Trie<K, V>) {
+        let other_root = match other.root {
+            Some(ref other_root) => other_root,
+            None => return,
+        };
+
+        if let Some(root) = self.root.take() {
+            let mut removed = 0;
+            self.root = root.difference(other_root, &mut removed);
+
+            if removed > 0 {
+                self.len -= removed;
+                self.invalidate_prefix_count_cache();
+            }
+        }
+    }
+
+
+    /// Remove every entry whose key is not present in `other`.
+    pub fn intersection_in_place(&mut self, other: &Trie<K, V>) {
+        let mut missing = Vec::new();
+
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut |leaf| if other.get_leaf(leaf.key).is_none() {
+                                          missing.push(leaf.key);
+                                      });
+        }
+
+        self.remove_bulk(&missing);
+    }
+
+
+    /// Remove every entry whose value equals `V::default()`, returning the number removed.
+    pub fn remove_empty_value_entries(&mut self) -> usize
+        where V: Default + PartialEq
+    {
+        let empty = V::default();
+        let mut keys = Vec::new();
+
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut |leaf| if leaf.val == empty {
+                                          keys.push(leaf.key);
+                                      });
+        }
+
+        self.remove_bulk(&keys)
+    }
+
+
+    /// Remove every entry for which `key_fn` returns the same bytes as for an earlier entry in key
+    /// order, returning the number of entries removed.
+    pub fn dedup_by_key<F>(&mut self, mut key_fn: F) -> usize
+        where F: FnMut(&K) -> &[u8]
+    {
+        let mut seen = HashSet::new();
+        let mut duplicates = Vec::new();
+
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut |leaf| if !seen.insert(key_fn(&leaf.key).to_vec()) {
+                                          duplicates.push(leaf.key);
+                                      });
+        }
+
+        self.remove_bulk(&duplicates)
+    }
+
+
+    /// Apply `f` to each entry in key order, stopping at and returning the first error.
+    pub fn apply_to_values<E, F>(&mut self, mut f: F) -> Result<(), E>
+        where F: FnMut(&K, &mut V) -> Result<(), E>
+    {
+        match self.root {
+            Some(ref mut root) => {
+                root.try_for_each_leaf_mut(&mut |leaf| f(&leaf.key, &mut leaf.val))
+            }
+            None => Ok(()),
+        }
+    }
+
+
+    /// Replace every value with the result of applying `f` to its key and the value. The shape of the
+    /// trie is preserved, and each value is replaced where it lies. If `f` panics, the trie is left
+    /// empty and its entries are leaked.
+    pub fn map_values_in_place<F: FnMut(&K, V) -> V>(&mut self, mut f: F) {
+        // The trie is emptied while `f` runs. Should it panic, the root is never put back or
+        // dropped, so the value moved out of its leaf cannot be seen or dropped again.
+        let len = mem::replace(&mut self.len, 0);
+
+        if let Some(root) = self.root.take() {
+            let mut root = mem::ManuallyDrop::new(root);
+            unsafe {
+                root.map_values_in_place(&mut f);
+            }
+            self.root = Some(mem::ManuallyDrop::into_inner(root));
+        }
+
+        self.len = len;
+    }
+
+
+    /// Split the trie into `n` tries covering consecutive ranges of keys, with sizes differing by at
+    /// most one entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is zero.
+    pub fn into_prefix_groups(self, n: usize) -> Vec<Trie<K, V>> {
+        assert!(n > 0, "cannot split a trie into zero groups");
+
+        let mut leaves = Vec::new();
+        if let Some(root) = self.root {
+            root.into_each_leaf(&mut |leaf| leaves.push(leaf));
+        }
+
+        let (size, remainder) = (leaves.len() / n, leaves.len() % n);
+        let mut leaves = leaves.into_iter().map(|leaf| (leaf.key, leaf.val));
+
+        (0..n)
+            .map(|i| {
+                     let group_size = if i < remainder { size + 1 } else { size };
+                     Trie::new().insert_batch_sorted(leaves.by_ref().take(group_size))
+                 })
+            .collect()
+    }
+
+
+    /// Split the trie at its median key into two tries of nearly equal size, the first holding the
+    /// lesser keys. When the number of entries is odd, the first trie holds the extra one.
+    pub fn split_at_midpoint(self) -> (Trie<K, V>, Trie<K, V>) {
+        let mut halves = self.into_prefix_groups(2).into_iter();
+
+        match (halves.next(), halves.next()) {
+            (Some(low), Some(high)) => (low, high),
+            _ => unsafe { debug_unreachable!() },
+        }
+    }
+
+
+    /// Merge any number of tries into one, keeping for each key the value from the last trie that
+    /// holds it. The tries are consumed in a single k-way merge of their entries, in key order.
+    pub fn merge_sorted_slices<I: IntoIterator<Item = Trie<K, V>>>(tries: I) -> Trie<K, V> {
+        let mut sources = tries.into_iter()
+            .map(|trie| IntoIter::new(trie.root, trie.len))
+            .collect::<Vec<_>>();
+
+        let mut heap = BinaryHeap::with_capacity(sources.len());
+        for (source, leaves) in sources.iter_mut().enumerate() {
+            if let Some(leaf) = leaves.next() {
+                heap.push(MergeHead { leaf, source });
+            }
+        }
+
+        let mut merged: Vec<(K, V)> = Vec::new();
+        while let Some(MergeHead { leaf, source }) = heap.pop() {
+            if let Some(next) = sources[source].next() {
+                heap.push(MergeHead { leaf: next, source });
+            }
+
+            // Equal keys come out in order of their sources, so a later one replaces the last.
+            match merged.last_mut() {
+                Some(last) if last.0.as_ref() == leaf.key.as_ref() => last.1 = leaf.val,
+                _ => merged.push((leaf.key, leaf.val)),
+            }
+        }
+
+        Trie::new().insert_batch_sorted(merged)
+    }
+
+
+    /// Merge another trie into this one. Values for keys present in both are combined with `f`,
+    /// and values for keys present only in `other` are converted with `Into`.
+    pub fn union_map<W, F>(mut self, other: Trie<K, W>, mut f: F) -> Trie<K, V>
+        where W: Into<V>,
+              F: FnMut(&K, V, W) -> V
+    {
+        if let Some(root) = other.root {
+            root.into_each_leaf(&mut |leaf: Leaf<K, W>| {
+                let val = match self.remove_leaf(leaf.key) {
+                    Some(existing) => f(&leaf.key, existing.val, leaf.val),
+                    None => leaf.val.into(),
+                };
+
+                self.insert(leaf.key, val);
+            });
+        }
+
+        self
+    }
+
+
+    /// Combine the values of the keys present in both this trie and `other` with `f`. Keys present
+    /// in only one of them are dropped.
+    pub fn zip_with<W, X, F: FnMut(V, W) -> X>(self, other: Trie<K, W>, mut f: F) -> Trie<K, X> {
+        let mut left = Vec::new();
+        if let Some(root) = self.root {
+            root.into_each_leaf(&mut |leaf| left.push(leaf));
+        }
+
+        let mut right = Vec::new();
+        if let Some(root) = other.root {
+            root.into_each_leaf(&mut |leaf| right.push(leaf));
+        }
+
+        // Both sides are in key order, so the shared keys can be found in a single merge pass and
+        // come out in order themselves.
+        let mut zipped = Vec::new();
+        let mut right = right.into_iter().peekable();
+
+        for l in left {
+            while right.next_if(|r| r.key.as_ref() < l.key.as_ref()).is_some() {}
+
+            if let Some(r) = right.next_if(|r| r.key.as_ref() == l.key.as_ref()) {
+                zipped.push((l.key, f(l.val, r.val)));
+            }
+        }
+
+        Trie::new().insert_batch_sorted(zipped)
+    }
+
+
+    /// Transform every entry with `f`, building a new trie from the resulting keys and values. If
+    /// `f` maps several entries to the same key, the one latest in the original key order wins.
+    pub fn map_entries<K2, W, F>(self, mut f: F) -> Trie<K2, W>
+        where K2: AsRef<[u8]> + AsMut<[u8]> + Copy,
+              F: FnMut(K, V) -> (K2, W)
+    {
+        let mut mapped = Trie::new();
+
+        if let Some(root) = self.root {
+            root.into_each_leaf(&mut |leaf| {
+                                    let (key, val) = f(leaf.key, leaf.val);
+                                    mapped.insert(key, val);
+                                });
+        }
+
+        mapped
+    }
+
+
+    /// Replace the whole contents of the trie with `replacement`, returning the old contents.
+    pub fn swap_out(&mut self, replacement: Trie<K, V>) -> Trie<K, V> {
+        mem::replace(self, replacement)
+    }
+
+
+    /// Decompose the trie into its raw internal state.
+    pub fn into_parts(self) -> TrieParts<K, V> {
+        TrieParts {
+            root: self.root,
+            len: self.len,
+        }
+    }
+
+
+    /// Reassemble a trie from its raw internal state.
+    ///
+    /// # Safety
+    ///
+    /// The parts must describe a valid trie, such as one returned by `into_parts`: every internal
+    /// node must have at least two children, its children must be stored under the nybbles of
+    /// their keys at its index, all keys beneath it must agree on the nybbles before its index,
+    /// and `len` must be the number of leaves. Operations on the trie assume this structure, and
+    /// may cause undefined behavior otherwise.
+    pub unsafe fn from_parts(parts: TrieParts<K, V>) -> Trie<K, V> {
+        Trie {
+            root: parts.root,
+            len: parts.len,
+            counts_valid: false,
+        }
+    }
+
+
+    /// Sum the lengths of all keys stored in the trie, in bytes.
+    pub fn total_key_bytes(&self) -> usize {
+        self.root.as_ref().map_or(0, Node::total_key_bytes)
+    }
+
+
+    /// Count the entries for which `f` returns `true`.
+    pub fn count_by<F: Fn(&K, &V) -> bool>(&self, f: F) -> usize {
+        let mut count = 0;
+
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut |leaf| if f(&leaf.key, &leaf.val) {
+                                          count += 1;
+                                      });
+        }
+
+        count
+    }
+
+
+    /// Call `f` with every entry in key order. The traversal keeps its own stack on the heap
+    /// rather than recursing, so it cannot overflow the call stack however deep the trie grows.
+    pub fn for_each_depth_first<F: FnMut(&K, &V)>(&self, mut f: F) {
+        for (key, val) in self.iter() {
+            f(key, val);
+        }
+    }
+
+
+    /// Call `f` with every entry in key order, with mutable access to the values, letting it skip
+    /// the rest of the smallest subtrie holding an entry or stop the traversal entirely.
+    pub fn depth_first_mut<F>(&mut self, mut f: F)
+        where F: FnMut(&K, &mut V) -> DepthFirstControl
+    {
+        if let Some(ref mut root) = self.root {
+            root.depth_first_mut(&mut f);
+        }
+    }
+
+
+    /// Call `f` with each entry whose key starts with `prefix`, in key order, until it returns
+    /// `false`. Returns the number of entries `f` was called with, including the last.
+    pub fn take_while_prefix<L, F>(&self, prefix: L, mut f: F) -> usize
+        where L: AsRef<[u8]>,
+              F: FnMut(&K, &V) -> bool
+    {
+        let mut visited = 0;
+
+        if let Some(subtrie) = self.root.as_ref().and_then(|root| root.get_prefix(prefix)) {
+            subtrie.find_leaf(&mut |leaf| {
+                                  visited += 1;
+                                  !f(&leaf.key, &leaf.val)
+                              });
+        }
+
+        visited
+    }
+
+
+    /// Call `f` with each entry whose key starts with `prefix`, in key order, stopping early if it
+    /// returns `ControlFlow::Break`. Returns `true` if every such entry was visited.
+    pub fn depth_first_search_prefix<L, F>(&self, prefix: L, mut f: F) -> bool
+        where L: AsRef<[u8]>,
+              F: FnMut(&K, &V) -> ControlFlow<()>
+    {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_prefix(prefix))
+            .and_then(|subtrie| subtrie.find_leaf(&mut |leaf| f(&leaf.key, &leaf.val).is_break()))
+            .is_none()
+    }
+
+
+    /// Returns `true` if `f` returns `true` for any entry, stopping at the first that does.
+    pub fn any<F: Fn(&K, &V) -> bool>(&self, f: F) -> bool {
+        self.root
+            .as_ref()
+            .and_then(|root| root.find_leaf(&mut |leaf| f(&leaf.key, &leaf.val)))
+            .is_some()
+    }
+
+
+    /// Returns `true` if `f` returns `true` for every entry, stopping at the first that does not.
+    pub fn all<F: Fn(&K, &V) -> bool>(&self, f: F) -> bool {
+        !self.any(|key, val| !f(key, val))
+    }
+
+
+    /// Returns `true` if any entry has a value equal to `val`. This checks every entry.
+    pub fn contains_value(&self, val: &V) -> bool
+        where V: PartialEq
+    {
+        self.any(|_, v| v == val)
+    }
+
+
+    /// Returns `true` if any entry whose key starts with `prefix` has a value equal to `val`.
+    pub fn contains_value_with_prefix<L: AsRef<[u8]>>(&self, prefix: L, val: &V) -> bool
+        where V: PartialEq
+    {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_prefix(prefix))
+            .and_then(|subtrie| subtrie.find_leaf(&mut |leaf| leaf.val == *val))
+            .is_some()
+    }
+
+
+    /// Find the first entry, in key order, for which `f` returns `true`.
+    pub fn find<F: Fn(&K, &V) -> bool>(&self, f: F) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .and_then(|root| root.find_leaf(&mut |leaf| f(&leaf.key, &leaf.val)))
+            .map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+
+    /// Check that iteration yields keys in strictly increasing order according to `K: Ord`,
+    /// returning the first adjacent pair of keys which are not.
+    pub fn validate_sorted_keys(&self) -> Result<(), (K, K)>
+        where K: Ord
+    {
+        let mut previous: Option<&K> = None;
+        let mut violation = None;
+
+        if let Some(ref root) = self.root {
+            root.find_leaf(&mut |leaf| {
+                if let Some(previous) = previous {
+                    if *previous >= leaf.key {
+                        violation = Some((*previous, leaf.key));
+                        return true;
+                    }
+                }
+
+                previous = Some(&leaf.key);
+                false
+            });
+        }
+
+        match violation {
+            Some(violation) => Err(violation),
+            None => Ok(()),
+        }
+    }
+
+
+    /// Returns `true` if the entries whose keys start with `prefix` are visited in non-decreasing
+    /// lexicographic order of their keys, as the structure of the trie guarantees.
+    pub fn verify_prefix_ordering<L: AsRef<[u8]>>(&self, prefix: L) -> bool {
+        let mut keys = Vec::new();
+
+        if let Some(subtrie) = self.root.as_ref().and_then(|root| root.get_prefix(prefix)) {
+            subtrie.for_each_leaf(&mut |leaf| keys.push(leaf.key.as_ref()));
+        }
+
+        keys.is_sorted()
+    }
+
+
+    /// Returns `true` if iteration yields keys in strictly increasing order according to `K: Ord`.
+    /// A trie always iterates in the lexicographic order of the key bytes, so this can only fail
+    /// for a key type whose `Ord` disagrees with that order; for arrays of bytes it cannot.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics with the first adjacent pair of keys which are out of order. As
+    /// above, this is unreachable for a key type whose `Ord` follows its bytes.
+    pub fn sort_check(&self) -> bool
+        where K: Ord + fmt::Debug
+    {
+        match self.validate_sorted_keys() {
+            Ok(()) => true,
+            Err((previous, next)) => {
+                debug_assert!(false, "keys out of order: {:?} before {:?}", previous, next);
+                false
+            }
+        }
+    }
+
+
+    /// Consume the trie, returning its entries in key order as a vector, which can be binary
+    /// searched by key for read-only use.
+    pub fn freeze_into_vec(self) -> Vec<(K, V)> {
+        let mut entries = Vec::with_capacity(self.len);
+
+        if let Some(root) = self.root {
+            root.into_each_leaf(&mut |leaf| entries.push((leaf.key, leaf.val)));
+        }
+
+        entries
+    }
+
+
+    /// Consume the trie, moving its entries into a hash map keyed by the keys read as UTF-8
+    /// strings. Fails on the first key, in key order, which is not valid UTF-8.
+    pub fn into_flattened_string_map(self) -> Result<HashMap<String, V>, str::Utf8Error> {
+        IntoIter::new(self.root, self.len)
+            .map(|leaf| Ok((str::from_utf8(leaf.key.as_ref())?.to_owned(), leaf.val)))
+            .collect()
+    }
+
+
+    /// Consume the trie, iterating over its keys in order.
+    pub fn into_keys(self) -> IntoKeys<K, V> {
+        IntoKeys { inner: IntoIter::new(self.root, self.len) }
+    }
+
+
+    /// Consume the trie, iterating over its values in key order.
+    pub fn into_values(self) -> IntoValues<K, V> {
+        IntoValues { inner: IntoIter::new(self.root, self.len) }
+    }
+
+
+    /// Iterate over the entries in reverse key order. This is equivalent to `iter().rev()`.
+    pub fn iter_in_reverse(&self) -> RevIter<'_, K, V> {
+        RevIter { inner: self.iter().rev() }
+    }
+
+
+    /// Iterate over the keys present in both this trie and `other`, in order, alongside the value
+    /// each trie stores for them. Both tries are walked in step, once each.
+    pub fn common_prefix_iter<'a>(&'a self,
+                                  other: &'a Trie<K, V>)
+                                  -> impl Iterator<Item = (&'a K, &'a V, &'a V)> {
+        let mut right = other.iter().peekable();
+
+        self.iter().filter_map(move |(key, l)| {
+            while right.next_if(|&(r, _)| r.as_ref() < key.as_ref()).is_some() {}
+
+            right.next_if(|&(r, _)| r.as_ref() == key.as_ref()).map(|(_, r)| (key, l, r))
+        })
+    }
+
+
+    /// Iterate, in key order, over the entries whose leaves lie beneath exactly `depth` internal
+    /// nodes. Nodes deeper than `depth` are never visited.
+    pub fn get_at_depth(&self, depth: usize) -> impl Iterator<Item = (&K, &V)> {
+        let mut stack = self.root.as_ref().map(|root| (root, 0)).into_iter().collect::<Vec<_>>();
+
+        iter::from_fn(move || {
+            while let Some((node, node_depth)) = stack.pop() {
+                match *node {
+                    Node::Leaf(ref leaf) if node_depth == depth => {
+                        return Some((&leaf.key, &leaf.val));
+                    }
+                    Node::Internal(ref internal) if node_depth < depth => {
+                        stack.extend(internal.nybbles.data.iter().rev().map(|child| {
+                            (child, node_depth + 1)
+                        }));
+                    }
+                    _ => {}
+                }
+            }
+
+            None
+        })
+    }
+
+
+    /// Iterate, in key order, over the entries whose keys have a first byte congruent to
+    /// `bucket_idx` modulo `bucket_count`. Iterating over each of the buckets `0..bucket_count`
+    /// yields every entry with a nonempty key exactly once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bucket_count` is zero.
+    pub fn bucket_iter(&self,
+                       bucket_count: usize,
+                       bucket_idx: usize)
+                       -> impl Iterator<Item = (&K, &V)> {
+        assert!(bucket_count > 0, "bucket count must be nonzero");
+
+        self.iter().filter(move |(key, _)| {
+            key.as_ref()
+                .first()
+                .is_some_and(|&byte| byte as usize % bucket_count == bucket_idx)
+        })
+    }
+
+
+    /// Iterate, in key order, over the entries with keys from `start` up to but not including
+    /// `end`.
+    pub fn iter_pairs_between<L: AsRef<[u8]>>(&self,
+                                              start: L,
+                                              end: L)
+                                              -> impl Iterator<Item = (&K, &V)> {
+        Iter::seek(self.root.as_ref(), start, self.len)
+            .take_while(move |(key, _)| key.as_ref() < end.as_ref())
+    }
+
+
+    /// Iterate, in order, over the distinct prefixes of `granularity` bytes among the keys in the
+    /// trie, each with a view of the entries under it. Keys shorter than `granularity` are
+    /// skipped.
+    pub fn prefix_ranges_iter(&self,
+                              granularity: usize)
+                              -> impl Iterator<Item = (Vec<u8>, Subtrie<'_, K, V>)> {
+        let mut previous: Option<&[u8]> = None;
+
+        self.iter().filter_map(move |(key, _)| {
+            let prefix = key.as_ref().get(..granularity)?;
+            if previous == Some(prefix) {
+                return None;
+            }
+            previous = Some(prefix);
+
+            self.root
+                .as_ref()
+                .and_then(|root| root.get_prefix(prefix))
+                .map(|root| (prefix.to_vec(), Subtrie { root }))
+        })
+    }
+
+
+    /// Iterate, in key order, over the entries whose keys start with `prefix`, except that the
+    /// nybble at `wildcard_nybble_idx` may take any value. A wildcard index past the end of
+    /// `prefix` has no effect.
+    pub fn get_prefix_with_wildcard<L: AsRef<[u8]>>(&self,
+                                                    prefix: L,
+                                                    wildcard_nybble_idx: usize)
+                                                    -> impl Iterator<Item = (&K, &V)> {
+        let prefix = prefix.as_ref().to_vec();
+        let wild = wildcard_nybble_idx < 2 * prefix.len();
+
+        // Each value of the wildcard nybble picks out a disjoint subtrie, and taking them in
+        // increasing order of that nybble keeps the entries in key order.
+        (0..if wild { 16 } else { 1 })
+            .filter_map(move |n| {
+                let mut prefix = prefix.clone();
+                if wild {
+                    let byte = &mut prefix[wildcard_nybble_idx / 2];
+                    *byte = if wildcard_nybble_idx & 1 == 0 {
+                        (*byte & 0x0f) | n << 4
+                    } else {
+                        (*byte & 0xf0) | n
+                    };
+                }
+
+                self.root.as_ref().and_then(|root| root.get_prefix(prefix))
+            })
+            .flat_map(|root| Subtrie { root }.iter())
+    }
+
+
+    /// Iterate over the entries in key order, in batches of at most `chunk_size`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn iter_chunks(&self, chunk_size: usize) -> impl Iterator<Item = Vec<(&K, &V)>> {
+        assert!(chunk_size > 0, "chunk size must be nonzero");
+
+        let mut iter = self.iter();
+        iter::from_fn(move || {
+                          let chunk = iter.by_ref().take(chunk_size).collect::<Vec<_>>();
+                          if chunk.is_empty() { None } else { Some(chunk) }
+                      })
+    }
+
+
+    /// Iterate over every entry in key order, alongside the length in bytes of the prefix its key
+    /// shares with `key`.
+    pub fn keys_sharing_prefix_with<L: AsRef<[u8]>>(&self,
+                                                    key: L)
+                                                    -> impl Iterator<Item = (&K, &V, usize)> {
+        self.iter().map(move |(k, v)| {
+            let shared = k.as_ref()
+                .iter()
+                .zip(key.as_ref())
+                .take_while(|&(a, b)| a == b)
+                .count();
+            (k, v, shared)
+        })
+    }
+
+
+    /// Count, for each `n`, the entries whose keys share at least `n` leading bytes with some other
+    /// key. The histogram ends at the longest prefix shared by any two keys, so it is empty if
+    /// there are fewer than two entries.
+    pub fn prefix_histogram(&self) -> Vec<usize> {
+        // In key order, the other key sharing the most with a key is one of its neighbours.
+        let neighbours = self.iter()
+            .zip(self.iter().skip(1))
+            .map(|((a, _), (b, _))| {
+                     a.as_ref()
+                         .iter()
+                         .zip(b.as_ref())
+                         .take_while(|&(a, b)| a == b)
+                         .count()
+                 })
+            .collect::<Vec<_>>();
+
+        let mut histogram = Vec::new();
+        if neighbours.is_empty() {
+            return histogram;
+        }
+
+        for i in 0..=neighbours.len() {
+            let before = i.checked_sub(1).map_or(0, |prev| neighbours[prev]);
+            let shared = cmp::max(before, neighbours.get(i).cloned().unwrap_or(0));
+            if histogram.len() <= shared {
+                histogram.resize(shared + 1, 0);
+            }
+            histogram[shared] += 1;
+        }
+
+        // Each entry has so far been counted only at its longest shared prefix.
+        for n in (1..histogram.len()).rev() {
+            histogram[n - 1] += histogram[n];
+        }
+
+        histogram
+    }
+
+
+    /// Find the entry whose key shares the longest common prefix with `key`. If several do, which
+    /// is returned is unspecified. Returns `None` only if the trie is empty.
+    pub fn nearest_neighbor<L: AsRef<[u8]>>(&self, key: L) -> Option<(&K, &V)> {
+        self.root
+            .as_ref()
+            .map(|root| root.get_closest_leaf(key))
+            .map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+
+    /// Select the `k` entries with the greatest values according to `cmp`, from greatest to least.
+    /// The order of entries with equal values is unspecified.
+    pub fn select_by<F: Fn(&V, &V) -> Ordering>(&self, k: usize, cmp: F) -> Vec<(&K, &V)> {
+        // `BinaryHeap` is a max-heap, so ordering entries in reverse keeps the least of the
+        // selected entries on top, ready to be evicted.
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+
+        if let Some(ref root) = self.root {
+            root.for_each_leaf(&mut |leaf| {
+                heap.push(Selected {
+                              leaf,
+                              cmp: &cmp,
+                          });
+
+                if heap.len() > k {
+                    heap.pop();
+                }
+            });
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|selected| (&selected.leaf.key, &selected.leaf.val))
+            .collect()
+    }
+
+
+    /// Iterate over the entries whose keys are prefixes of `key`, including `key` itself if it is
+    /// stored.
+    pub fn prefixes_of<L: AsRef<[u8]>>(&self, key: L) -> PrefixesOf<'_, K, V> {
+        // Any stored prefix of `key` agrees with it on every nybble it has, and so can only be
+        // the leaf at the end of `key`'s path.
+        let leaf = self.root
+            .as_ref()
+            .and_then(|root| root.get_leaf_on_path(&key))
+            .filter(|leaf| key.as_ref().starts_with(leaf.key.as_ref()));
+
+        PrefixesOf { leaf }
+    }
+
+
+    /// Serialize the entries whose keys start with `prefix` as a sequence of key-value pairs, in
+    /// key order.
+    #[cfg(feature = "serde")]
+    pub fn serialize_prefix<L, S>(&self, prefix: L, serializer: S) -> Result<S::Ok, S::Error>
+        where L: AsRef<[u8]>,
+              S: serde::Serializer,
+              K: serde::Serialize,
+              V: serde::Serialize
+    {
+        let subtrie = self.root.as_ref().and_then(|root| root.get_prefix(prefix));
+        let len = subtrie.map_or(0, Node::count_leaves);
+
+        serializer.collect_seq(Iter::new(subtrie, len))
+    }
+
+
+    /// Find the entries with the greatest key less than `key` and the least key greater than it,
+    /// with a single descent of the trie.
+    #[allow(clippy::type_complexity)]
+    pub fn get_adjacent<L: AsRef<[u8]>>(&self,
+                                        key: L)
+                                        -> (Option<(&K, &V)>, Option<(&K, &V)>) {
+        let (before, after) = match self.root {
+            Some(ref root) => root.get_adjacent(key),
+            None => (None, None),
+        };
+
+        (before.map(Node::get_last_leaf).map(|leaf| (&leaf.key, &leaf.val)),
+         after.map(Node::get_first_leaf).map(|leaf| (&leaf.key, &leaf.val)))
+    }
+
+
+    /// Check whether every one of `keys` is present in the trie, stopping at the first which is
+    /// not. The keys are looked up in a single walk of the trie, so those sharing a prefix share
+    /// the descent to it; keys which are not already sorted are sorted first.
+    pub fn contains_all_of_slice<L: AsRef<[u8]>>(&self, keys: &[L]) -> bool {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return keys.is_empty(),
+        };
+
+        if keys.windows(2).all(|pair| pair[0].as_ref() <= pair[1].as_ref()) {
+            root.contains_sorted(keys)
+        } else {
+            let mut sorted = keys.iter().map(AsRef::as_ref).collect::<Vec<&[u8]>>();
+            sorted.sort_unstable();
+            root.contains_sorted(&sorted)
+        }
+    }
+
+
+    /// Get the value stored under the longest key which is a prefix of `key`, including `key`
+    /// itself.
+    pub fn longest_prefix_match_value<L: AsRef<[u8]>>(&self, key: L) -> Option<&V> {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_leaf_on_path(&key))
+            .filter(|leaf| key.as_ref().starts_with(leaf.key.as_ref()))
+            .map(|leaf| &leaf.val)
+    }
+
+
+    /// Copy the entries whose keys start with `prefix` into a new trie, leaving this one untouched.
+    pub fn clone_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> Trie<K, V>
+        where V: Clone
+    {
+        Trie::from_root(self.root
+                            .as_ref()
+                            .and_then(|root| root.get_prefix(prefix))
+                            .cloned())
+    }
+
+
+    /// Copy the entries whose keys do not start with `prefix` into a new trie, leaving this one
+    /// untouched. The entries which do are never copied: only the subtries beside the path down to
+    /// them are.
+    pub fn clone_without_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> Trie<K, V>
+        where V: Clone
+    {
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return Trie::new(),
+        };
+
+        match root.get_prefix(&prefix) {
+            Some(subtrie) => {
+                Trie {
+                    root: root.clone_outside(subtrie, prefix.as_ref()),
+                    len: self.len - subtrie.count_leaves(),
+                    counts_valid: false,
+                }
+            }
+            None => {
+                Trie {
+                    root: Some(root.clone()),
+                    len: self.len,
+                    counts_valid: false,
+                }
+            }
+        }
+    }
+
+
+    /// Copy the entries whose keys start with `prefix` into a new trie. This is another name for
+    /// `clone_prefix`.
+    pub fn filter_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> Trie<K, V>
+        where V: Clone
+    {
+        self.clone_prefix(prefix)
+    }
+
+
+    /// Clone the subtrie of entries whose keys start with `prefix` into a new, independently owned
+    /// trie. This is another name for `clone_prefix`, whose copy is already deep: keys are `Copy`,
+    /// and each value is cloned.
+    pub fn deep_clone_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> Trie<K, V>
+        where V: Clone
+    {
+        self.clone_prefix(prefix)
+    }
+
+
+    /// Find the greatest nybble index at which both this trie and `other` have an internal node
+    /// for the same key prefix, measuring how deeply their key spaces overlap. This is also zero
+    /// if they share no internal node at all.
+    pub fn max_shared_prefix_depth(&self, other: &Trie<K, V>) -> usize {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other)) => root.max_shared_index(other).unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+
+    /// For each depth at which there are internal nodes, compute a mask with bit `n` set if any
+    /// internal node at that depth has a child for nybble `n`.
+    pub fn count_reachable_nybbles(&self) -> Vec<u32> {
+        let mut masks = Vec::new();
+
+        if let Some(ref root) = self.root {
+            root.count_reachable_nybbles(0, &mut masks);
+        }
+
+        masks
+    }
+
+
+    /// List every edge in the trie, in preorder, as the whole bytes shared by the keys beneath the
+    /// parent, the same for the child, and the nybble the edge is labelled with. A leaf's prefix is
+    /// its entire key.
+    pub fn export_edges(&self) -> Vec<(Vec<u8>, Vec<u8>, u8)> {
+        let mut edges = Vec::new();
+
+        if let Some(ref root) = self.root {
+            root.export_edges(&mut edges);
+        }
+
+        edges
+    }
+
+
+    /// Count the distinct prefixes of length `byte_depth` among the keys in the trie. Keys shorter
+    /// than `byte_depth` are not counted.
+    pub fn count_distinct_prefixes(&self, byte_depth: usize) -> usize {
+        self.root
+            .as_ref()
+            .map_or(0, |root| root.count_distinct_prefixes(byte_depth))
+    }
+
+
+    /// Find the shortest prefix length, in bytes, at which the keys have at least
+    /// `target_shard_count` distinct prefixes. If no length does, this is the length of the
+    /// longest key, where the keys are most spread out.
+    pub fn compute_optimal_prefix_length(&self, target_shard_count: usize) -> usize {
+        let max_len = self.max_key_len().unwrap_or(0);
+
+        (0..max_len)
+            .find(|&len| self.count_distinct_prefixes(len) >= target_shard_count)
+            .unwrap_or(max_len)
+    }
+
+
+    /// Count the leading nybbles shared by every key in the trie: the whole key if there is only
+    /// one, and zero if there are none.
+    pub fn common_prefix_nybbles(&self) -> usize {
+        match self.root {
+            // The root branches on the first nybble at which its keys differ.
+            Some(Node::Internal(ref internal)) => internal.index,
+            Some(Node::Leaf(ref leaf)) => 2 * leaf.key.as_ref().len(),
+            None => 0,
+        }
+    }
+
+
+    /// Compute the fraction of all byte strings of length `sample_len` which are prefixes of some
+    /// key in the trie.
+    pub fn key_space_coverage(&self, sample_len: usize) -> f64 {
+        self.count_distinct_prefixes(sample_len) as f64 / 256f64.powi(sample_len as i32)
+    }
+
+
+    /// Call `f` for each internal node, in preorder, with the node's depth from the root, the whole
+    /// bytes shared by every key beneath it, and its number of children.
+    pub fn walk_internal_nodes<F: FnMut(usize, &[u8], u8)>(&self, mut f: F) {
+        if let Some(ref root) = self.root {
+            root.walk_internal_nodes(0, &mut f);
+        }
+    }
+
+
+    /// Count the internal nodes and leaves, respectively, exactly `depth` hops from the root.
+    pub fn count_nodes_at_depth(&self, depth: usize) -> (usize, usize) {
+        self.root
+            .as_ref()
+            .map_or((0, 0), |root| root.count_nodes_at_depth(depth))
+    }
+
+
+    /// Count the distinct key prefixes of each length from one byte up to the longest key. Every
+    /// key has the length of a `K`, so a prefix of that length is a whole key, and there are
+    /// `len()` of them.
+    pub fn prefixes_len_distribution(&self) -> BTreeMap<usize, usize> {
+        let key_len = self.max_key_len().unwrap_or(0);
+
+        (1..key_len)
+            .map(|len| (len, self.count_distinct_prefixes(len)))
+            .chain(if key_len > 0 { Some((key_len, self.len)) } else { None })
+            .collect()
+    }
+
+
+    /// Count the keys starting with each distinct prefix of `prefix_len` bytes. Keys shorter than
+    /// `prefix_len` are not counted.
+    pub fn frequency_map(&self, prefix_len: usize) -> BTreeMap<Vec<u8>, usize> {
+        let mut frequencies = BTreeMap::<Vec<u8>, usize>::new();
+
+        // Keys come out in order, so a key either extends the run of the greatest prefix seen so
+        // far or starts a new greatest prefix.
+        for (key, _) in self.iter() {
+            if let Some(prefix) = key.as_ref().get(..prefix_len) {
+                match frequencies.iter_mut().next_back() {
+                    Some((last, count)) if &last[..] == prefix => *count += 1,
+                    _ => {
+                        frequencies.insert(prefix.to_vec(), 1);
+                    }
+                }
+            }
+        }
+
+        frequencies
+    }
+
+
+    /// Find the prefix of `prefix_len` bytes shared by the greatest number of keys, or `None` if no
+    /// key is that long. Ties go to the least such prefix.
+    pub fn most_common_prefix(&self, prefix_len: usize) -> Option<Vec<u8>> {
+        let mut most_common: Option<(Vec<u8>, usize)> = None;
+
+        for (prefix, count) in self.frequency_map(prefix_len) {
+            if most_common.as_ref().is_none_or(|&(_, max)| count > max) {
+                most_common = Some((prefix, count));
+            }
+        }
+
+        most_common.map(|(prefix, _)| prefix)
+    }
+
+
+    /// Compute the average number of children of an internal node, or NaN if there are no internal
+    /// nodes. Uniformly distributed keys give a branching factor near 16.
+    pub fn branch_factor(&self) -> f64 {
+        let (nodes, edges) = self.root.as_ref().map_or((0, 0), Node::count_internal);
+        edges as f64 / nodes as f64
+    }
+
+
+    /// Summarize the trie over a few lines: the number of entries, its height, its branching
+    /// factor, and the five one-byte prefixes shared by the most keys, with their counts.
+    pub fn to_compact_string(&self) -> String {
+        use std::fmt::Write;
+
+        let mut prefixes = self.frequency_map(1).into_iter().collect::<Vec<_>>();
+        prefixes.sort_by_key(|&(_, count)| cmp::Reverse(count));
+
+        // Writing to a `String` cannot fail.
+        let mut summary = String::new();
+        let _ = writeln!(summary, "entries: {}", self.len());
+        let _ = writeln!(summary,
+                         "height: {}",
+                         self.root.as_ref().map_or(0, Node::height));
+        let _ = writeln!(summary, "branching factor: {:.2}", self.branch_factor());
+        let _ = writeln!(summary, "top prefixes:");
+        for (prefix, count) in prefixes.into_iter().take(5) {
+            let _ = writeln!(summary, "  {:02x}: {}", prefix[0], count);
+        }
+
+        summary
+    }
+
+
+    /// Compute the fraction of all nodes which are leaves, or NaN if the trie is empty. A ratio
+    /// near 1.0 means little overhead from internal nodes; a ratio near 0.5 means almost every
+    /// internal node has only two children.
+    pub fn entry_ratio(&self) -> f64 {
+        match self.root {
+            Some(ref root) => {
+                // Every node but the root is the target of exactly one edge.
+                let (internal, edges) = root.count_internal();
+                (edges + 1 - internal) as f64 / (edges + 1) as f64
+            }
+            None => f64::NAN,
+        }
+    }
+
+
+    /// Replace every internal node with only one child by that child. Removals already collapse
+    /// such nodes as they go, so this only repairs a trie whose nodes have been rearranged some
+    /// other way.
+    pub fn coalesce(&mut self) {
+        // A collapsed node has the same leaves beneath it as its child, so prefix counts survive.
+        if let Some(ref mut root) = self.root {
+            root.coalesce();
+        }
+    }
+
+
+    /// Returns `true` if both tries have exactly the same shape: the same internal nodes branching
+    /// at the same indices, and the same leaf keys in the same places. Values are not compared.
+    pub fn compare_structure(&self, other: &Trie<K, V>) -> bool
+        where K: PartialEq
+    {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other)) => root.compare_structure(other),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+
+    /// Count the entries with zero-length keys, which is at most one. The empty key sorts before
+    /// every other, so only the first entry need be checked.
+    pub fn count_empty_key_entries(&self) -> usize {
+        self.root
+            .as_ref()
+            .map(Node::get_first_leaf)
+            .filter(|leaf| leaf.key.as_ref().is_empty())
+            .map_or(0, |_| 1)
+    }
+
+
+    /// Compute the height of the subtrie of entries whose keys start with `prefix`: the greatest
+    /// number of internal nodes between its root and any of its leaves. A subtrie of a single
+    /// entry, or none, has height zero.
+    pub fn subtrie_height<L: AsRef<[u8]>>(&self, prefix: L) -> usize {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_prefix(prefix))
+            .map_or(0, Node::height)
+    }
+
+
+    /// The length in bytes of the shortest key in the trie, or `None` if it is empty.
+    pub fn min_key_len(&self) -> Option<usize> {
+        // Every key is built to the length of a `K`, so either extremity of the trie will do.
+        self.root
+            .as_ref()
+            .map(|root| root.get_first_leaf().key.as_ref().len())
+    }
+
+
+    /// The length in bytes of the longest key in the trie, or `None` if it is empty.
+    pub fn max_key_len(&self) -> Option<usize> {
+        self.root
+            .as_ref()
+            .map(|root| root.get_last_leaf().key.as_ref().len())
+    }
+
+
+    /// Find the prefix of `prefix_byte_len` bytes whose subtrie is the tallest, as measured by
+    /// `subtrie_height`, or `None` if no key is that long. Ties go to the least such prefix.
+    pub fn max_depth_prefix(&self, prefix_byte_len: usize) -> Option<Vec<u8>> {
+        let mut deepest: Option<(&[u8], usize)> = None;
+        let mut previous: Option<&[u8]> = None;
+
+        // Keys come out in order, so each distinct prefix is seen in one consecutive run.
+        for (key, _) in self.iter() {
+            let prefix = match key.as_ref().get(..prefix_byte_len) {
+                Some(prefix) if previous != Some(prefix) => prefix,
+                _ => continue,
+            };
+            previous = Some(prefix);
+
+            let height = self.subtrie_height(prefix);
+            if deepest.is_none_or(|(_, max)| height > max) {
+                deepest = Some((prefix, height));
+            }
+        }
+
+        deepest.map(|(prefix, _)| prefix.to_vec())
+    }
+
+
+    /// Count the child slots allocated across all internal nodes, whether or not they are occupied.
+    pub fn capacity(&self) -> usize {
+        self.root.as_ref().map_or(0, Node::capacity)
+    }
+
+
+    /// Reserve child slots for `additional` more branches in each internal node of the subtrie of
+    /// keys starting with `prefix`, up to the sixteen any node can hold. If at most one key starts
+    /// with `prefix` the subtrie has no internal nodes, and nothing is reserved.
+    pub fn reserve_prefix<L: AsRef<[u8]>>(&mut self, prefix: L, additional: usize) {
+        if let Some(subtrie) = self.root.as_mut().and_then(|root| root.get_prefix_mut(prefix)) {
+            subtrie.reserve(additional);
+        }
+    }
+
+
+    /// Returns `true` if no key in the trie is a proper prefix of another. Every key is built to
+    /// the length of a `K`, so no key can be a proper prefix of another, and this always holds.
+    pub fn is_prefix_free(&self) -> bool {
+        true
+    }
+
+
+    /// Returns `true` if both this trie and `other` hold at least one key starting with `prefix`.
+    pub fn prefix_overlap<L: AsRef<[u8]>>(&self, other: &Trie<K, V>, prefix: L) -> bool {
+        self.contains_prefix(&prefix) && other.contains_prefix(&prefix)
+    }
+
+
+    /// Returns `true` if any key in the trie falls within `range`, usually given as a pair of
+    /// `Bound<&[u8]>`. The trie is descended straight to the start of the range, so only the
+    /// first key past it need be compared against its end.
+    pub fn contains_range<R: RangeBounds<[u8]>>(&self, range: R) -> bool {
+        let start = match range.start_bound() {
+            Bound::Included(start) | Bound::Excluded(start) => start,
+            Bound::Unbounded => &[],
+        };
+
+        // An excluded start may itself be stored, in which case the key after it is wanted.
+        Iter::seek(self.root.as_ref(), start, self.len)
+            .map(|(key, _)| key.as_ref())
+            .find(|key| match range.start_bound() {
+                      Bound::Excluded(start) => *key > start,
+                      _ => true,
+                  })
+            .is_some_and(|key| range.contains(key))
+    }
+
+
+    /// Returns `true` if any key in the trie starts with `prefix`, whether or not `prefix` itself
+    /// is stored as a key.
+    pub fn contains_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> bool {
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_prefix(prefix))
+            .is_some()
+    }
+
+
+    /// Returns whether `prefix` is itself stored as a key, and whether any longer key starts with
+    /// it.
+    pub fn prefix_exists_exactly<L: AsRef<[u8]>>(&self, prefix: L) -> (bool, bool) {
+        match self.root.as_ref().and_then(|root| root.get_prefix(&prefix)) {
+            // At most one of the keys beneath an internal node can be `prefix` itself.
+            Some(Node::Internal(..)) => (self.get_leaf(&prefix).is_some(), true),
+            Some(Node::Leaf(leaf)) => {
+                let exact = leaf.key.as_ref() == prefix.as_ref();
+                (exact, !exact)
+            }
+            None => (false, false),
+        }
+    }
+
+
+    /// Count the entries whose keys start with `prefix`. Once `build_prefix_count_cache` has been
+    /// called, this only walks down to the subtrie of such entries until the trie is next modified;
+    /// otherwise the leaves of that subtrie are counted. The empty prefix is always answered from
+    /// `len()`.
+    pub fn len_at_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> usize {
+        if prefix.as_ref().is_empty() {
+            return self.len;
+        }
+
+        match self.root.as_ref().and_then(|root| root.get_prefix(prefix)) {
+            Some(Node::Internal(internal)) if self.counts_valid => internal.count,
+            Some(subtrie) => subtrie.count_leaves(),
+            None => 0,
+        }
+    }
+
+
+    /// Count the entries whose keys start with `prefix`. This is equivalent to `len_at_prefix`,
+    /// which should be preferred.
+    pub fn count_with_prefix<L: AsRef<[u8]>>(&self, prefix: L) -> usize {
+        self.len_at_prefix(prefix)
+    }
+
+
+    /// Count the entries whose keys start with `prefix` without iterating over them. This is
+    /// another name for `len_at_prefix`, which should be preferred.
+    pub fn prefix_iter_count<L: AsRef<[u8]>>(&self, prefix: L) -> usize {
+        self.len_at_prefix(prefix)
+    }
+
+
+    /// Get the `n`th entry in key order, counting from zero. Once `build_prefix_count_cache` has
+    /// been called, this takes time proportional to the height of the trie, until the trie is next
+    /// modified; otherwise it iterates over the first `n` entries.
+    pub fn get_entry_at_nth_position(&self, n: usize) -> Option<(&K, &V)> {
+        if !self.counts_valid {
+            return self.iter().nth(n);
+        }
+
+        self.root
+            .as_ref()
+            .and_then(|root| root.get_nth_leaf_counted(n))
+            .map(|leaf| (&leaf.key, &leaf.val))
+    }
+
+
+    /// Record the number of entries beneath every internal node, so that `len_at_prefix`
+    /// need not walk the entries themselves.
+    pub fn build_prefix_count_cache(&mut self) {
+        if let Some(ref mut root) = self.root {
+            root.build_counts();
+        }
+        self.counts_valid = true;
+    }
+
+
+    /// Mark the cached entry counts as stale, so that `len_at_prefix` counts entries directly
+    /// until `build_prefix_count_cache` is called again. Every modification of the trie's
+    /// structure calls this.
+    pub fn invalidate_prefix_count_cache(&mut self) {
+        self.counts_valid = false;
+    }
+}
+
+
+/// The raw internal state of a trie, for inspecting its structure directly. Its nodes can be
+/// examined through `Internal::index`, `Internal::child` and `Internal::children`, and the
+/// `Leaf::key` and `Leaf::val` of each leaf.
+pub struct TrieParts<K, V> {
+    pub root: Option<Node<K, V>>,
+    /// The number of entries in the trie.
+    pub len: usize,
+}
+
+
+/// A leaf ordered in reverse by a comparator on its value, for `Trie::select_by`.
+struct Selected<'a, 'f, K: 'a, V: 'a, F: 'f> {
+    leaf: &'a Leaf<K, V>,
+    cmp: &'f F,
+}
+
+
+impl<'a, 'f, K, V, F: Fn(&V, &V) -> Ordering> PartialEq for Selected<'a, 'f, K, V, F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+impl<'a, 'f, K, V, F: Fn(&V, &V) -> Ordering> Eq for Selected<'a, 'f, K, V, F> {}
+
+
+impl<'a, 'f, K, V, F: Fn(&V, &V) -> Ordering> PartialOrd for Selected<'a, 'f, K, V, F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl<'a, 'f, K, V, F: Fn(&V, &V) -> Ordering> Ord for Selected<'a, 'f, K, V, F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.cmp)(&other.leaf.val, &self.leaf.val)
+    }
+}
+
+
+// The least remaining leaf of one of the tries being merged by `merge_sorted_slices`, ordered so
+// that a max-heap yields the least key first, and equal keys in order of their sources.
+struct MergeHead<K, V> {
+    leaf: Leaf<K, V>,
+    source: usize,
+}
+
+
+impl<K: AsRef<[u8]>, V> PartialEq for MergeHead<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+impl<K: AsRef<[u8]>, V> Eq for MergeHead<K, V> {}
+
+
+impl<K: AsRef<[u8]>, V> PartialOrd for MergeHead<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl<K: AsRef<[u8]>, V> Ord for MergeHead<K, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.leaf.key.as_ref(), other.source).cmp(&(self.leaf.key.as_ref(), self.source))
+    }
+}
+
+
+/// The error returned when removing a key which is not present in a trie.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KeyNotFound;
+
+
+impl fmt::Display for KeyNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("key not found in trie")
+    }
+}
+
+
+impl Error for KeyNotFound {}
+
+
+/// What `Trie::depth_first_mut` should do after visiting an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthFirstControl {
+    /// Go on to the next entry.
+    Continue,
+    /// Skip the entries after this one in the smallest subtrie holding it: its later siblings in
+    /// the trie and everything beneath them.
+    SkipSubtrie,
+    /// End the traversal.
+    Stop,
+}
+
+
+/// A view into a single entry of a trie, which may be either occupied or vacant.
+pub enum Entry<'a, K: 'a, V: 'a> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+
+impl<'a, K: AsRef<[u8]> + AsMut<[u8]> + Copy, V> Entry<'a, K, V> {
+    /// Ensure a value is present by inserting `default` if the entry is vacant, and return a
+    /// mutable reference to it.
+    pub fn or_insert(self, default: V) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+
+    /// Ensure a value is present by inserting the result of `default` if the entry is vacant, and
+    /// return a mutable reference to it.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> &'a mut V {
+        match self {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+}
+
+
+/// An occupied entry of a trie.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+    leaf: &'a mut Leaf<K, V>,
+}
+
+
+impl<'a, K, V> OccupiedEntry<'a, K, V> {
+    pub fn key(&self) -> &K {
+        &self.leaf.key
+    }
+
+
+    pub fn get(&self) -> &V {
+        &self.leaf.val
+    }
+
+
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.leaf.val
+    }
+
+
+    pub fn into_mut(self) -> &'a mut V {
+        &mut self.leaf.val
+    }
+
+
+    /// Replace the entry's value, returning the old one.
+    pub fn insert(&mut self, val: V) -> V {
+        mem::replace(&mut self.leaf.val, val)
+    }
+}
+
+
+/// A vacant entry of a trie. The key is held as raw bytes, and only converted to a `K` when a
+/// value is inserted.
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+    trie: &'a mut Trie<K, V>,
+    key: Vec<u8>,
+}
+
+
+impl<'a, K: AsRef<[u8]> + AsMut<[u8]> + Copy, V> VacantEntry<'a, K, V> {
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+
+    /// Insert a value into the trie at this entry's key, returning a mutable reference to it.
+    pub fn insert(self, val: V) -> &'a mut V {
+        let VacantEntry { trie, key } = self;
+        trie.insert(&key, val);
+
+        match trie.get_leaf_mut(&key) {
+            Some(leaf) => &mut leaf.val,
+            None => unsafe { debug_unreachable!() },
+        }
+    }
+}
+
+
+/// An iterator over the entries of a trie, in key order.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    // The front and back of the iteration each walk the whole trie from opposite ends; `remaining`
+    // stops them once they meet.
+    front: Vec<slice::Iter<'a, Node<K, V>>>,
+    back: Vec<slice::Iter<'a, Node<K, V>>>,
+    remaining: usize,
+}
+
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn new(root: Option<&'a Node<K, V>>, len: usize) -> Iter<'a, K, V> {
+        let stack = root.map(|root| slice::from_ref(root).iter())
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        Iter {
+            front: stack.clone(),
+            back: stack,
+            remaining: len,
+        }
+    }
+}
+
+
+impl<'a, K: AsRef<[u8]>, V> Iter<'a, K, V> {
+    /// Start iterating forwards from the least key no less than `start`, without visiting any key
+    /// before it. Only the front of the returned iterator may be used, and `len` need only bound
+    /// the number of keys it yields.
+    fn seek<L: AsRef<[u8]>>(root: Option<&'a Node<K, V>>, start: L, len: usize) -> Iter<'a, K, V> {
+        let start = start.as_ref();
+        let mut front = Vec::new();
+
+        // This is the descent of `Node::get_adjacent`, except that it keeps the later siblings of
+        // each node on the way down, which are exactly the subtries left to iterate.
+        if let Some(mut node) = root {
+            let mismatch = nybble_mismatch(node.get_closest_leaf(start).key.as_ref(), start);
+            let start_nybble = mismatch.and_then(|index| try_nybble(index, start));
+
+            loop {
+                match *node {
+                    Node::Internal(ref internal) if mismatch.is_none_or(|m| internal.index < m) => {
+                        let nybbles = &internal.nybbles;
+                        let actual = nybbles.actual(nybble(internal.index, start) as usize);
+
+                        front.push(nybbles.data[actual + 1..].iter());
+                        node = &nybbles.data[actual];
+                    }
+                    Node::Internal(ref internal) if mismatch == Some(internal.index) => {
+                        let nybbles = &internal.nybbles;
+                        let actual = start_nybble.map_or(0, |n| nybbles.actual(n as usize));
+
+                        front.push(nybbles.data[actual..].iter());
+                        break;
+                    }
+                    _ => {
+                        let after = match mismatch {
+                            Some(index) => {
+                                try_nybble(index, &node.get_first_leaf().key) > start_nybble
+                            }
+                            None => true,
+                        };
+
+                        if after {
+                            front.push(slice::from_ref(node).iter());
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        Iter {
+            front,
+            back: Vec::new(),
+            remaining: len,
+        }
+    }
+}
+
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        loop {
+            match self.front.last_mut()?.next() {
+                Some(Node::Leaf(leaf)) => {
+                    self.remaining -= 1;
+                    return Some((&leaf.key, &leaf.val));
+                }
+                Some(Node::Internal(internal)) => {
+                    self.front.push(internal.nybbles.data.iter())
+                }
+                None => {
+                    self.front.pop();
+                }
+            }
+        }
+    }
+
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+impl<'a, K, V> DoubleEndedIterator for Iter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        loop {
+            match self.back.last_mut()?.next_back() {
+                Some(Node::Leaf(leaf)) => {
+                    self.remaining -= 1;
+                    return Some((&leaf.key, &leaf.val));
+                }
+                Some(Node::Internal(internal)) => {
+                    self.back.push(internal.nybbles.data.iter())
+                }
+                None => {
+                    self.back.pop();
+                }
+            }
+        }
+    }
+}
+
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V> {}
+
+
+impl<'a, K, V> FusedIterator for Iter<'a, K, V> {}
+
+
+/// An iterator over the entries of a trie, in reverse key order.
+pub struct RevIter<'a, K: 'a, V: 'a> {
+    inner: iter::Rev<Iter<'a, K, V>>,
+}
+
+
+impl<'a, K, V> Iterator for RevIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next()
+    }
+
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+
+impl<'a, K, V> DoubleEndedIterator for RevIter<'a, K, V> {
+    fn next_back(&mut self) -> Option<(&'a K, &'a V)> {
+        self.inner.next_back()
+    }
+}
+
+
+impl<'a, K, V> ExactSizeIterator for RevIter<'a, K, V> {}
+
+
+impl<'a, K, V> FusedIterator for RevIter<'a, K, V> {}
+
+
+/// A borrowed view of the entries of a trie whose keys share a prefix.
+pub struct Subtrie<'a, K: 'a, V: 'a> {
+    root: &'a Node<K, V>,
+}
+
+
+impl<'a, K: AsRef<[u8]>, V> Subtrie<'a, K, V> {
+    /// The number of entries in the subtrie. This counts them.
+    pub fn len(&self) -> usize {
+        self.root.count_leaves()
+    }
+
+
+    /// Returns `false`, as a subtrie always holds at least one entry.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+
+    /// Iterate over the entries of the subtrie in key order.
+    pub fn iter(&self) -> Iter<'a, K, V> {
+        Iter::new(Some(self.root), self.len())
+    }
+}
+
+
+/// An owning iterator over the entries of a trie, in key order.
+struct IntoIter<K, V> {
+    stack: Vec<vec::IntoIter<Node<K, V>>>,
+    remaining: usize,
+}
+
+
+impl<K, V> IntoIter<K, V> {
+    fn new(root: Option<Node<K, V>>, len: usize) -> IntoIter<K, V> {
+        IntoIter {
+            stack: root.map(|root| vec![root].into_iter()).into_iter().collect(),
+            remaining: len,
+        }
+    }
+}
+
+
+impl<K, V> Iterator for IntoIter<K, V> {
+    type Item = Leaf<K, V>;
+
+    fn next(&mut self) -> Option<Leaf<K, V>> {
+        loop {
+            match self.stack.last_mut()?.next() {
+                Some(Node::Leaf(leaf)) => {
+                    self.remaining -= 1;
+                    return Some(leaf);
+                }
+                Some(Node::Internal(internal)) => {
+                    self.stack.push(internal.nybbles.data.into_iter())
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+
+/// An owning iterator over the keys of a trie, in order.
+pub struct IntoKeys<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+
+impl<K, V> Iterator for IntoKeys<K, V> {
+    type Item = K;
+
+    fn next(&mut self) -> Option<K> {
+        self.inner.next().map(|leaf| leaf.key)
+    }
+
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+
+impl<K, V> ExactSizeIterator for IntoKeys<K, V> {}
+
+
+impl<K, V> FusedIterator for IntoKeys<K, V> {}
+
+
+/// An owning iterator over the values of a trie, in key order.
+pub struct IntoValues<K, V> {
+    inner: IntoIter<K, V>,
+}
+
+
+impl<K, V> Iterator for IntoValues<K, V> {
+    type Item = V;
+
+    fn next(&mut self) -> Option<V> {
+        self.inner.next().map(|leaf| leaf.val)
+    }
+
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+
+impl<K, V> ExactSizeIterator for IntoValues<K, V> {}
+
+
+impl<K, V> FusedIterator for IntoValues<K, V> {}
+
+
+/// An iterator over the entries of a trie whose keys are prefixes of a given key.
+pub struct PrefixesOf<'a, K: 'a, V: 'a> {
+    leaf: Option<&'a Leaf<K, V>>,
+}
+
+
+impl<'a, K, V> Iterator for PrefixesOf<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        self.leaf.take().map(|leaf| (&leaf.key, &leaf.val))
+    }
+}
+
+
+impl<V> Trie<[u8; 8], V> {
+    /// Convert a trie keyed by eight-byte arrays into a map keyed by the big-endian `u64`s they
+    /// represent.
+    pub fn into_index_map(self) -> BTreeMap<u64, V> {
+        let mut map = BTreeMap::new();
+
+        if let Some(root) = self.root {
+            root.into_each_leaf(&mut |leaf| {
+                                     map.insert(u64::from_be_bytes(leaf.key), leaf.val);
+                                 });
+        }
+
+        map
+    }
+}
+
+
+impl<K: AsRef<[u8]> + AsMut<[u8]> + Copy, V> Default for Trie<K, V> {
+    fn default() -> Trie<K, V> {
+        Trie::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic;
+
+
+    fn keys<K: Copy, V>(trie: &Trie<K, V>) -> Vec<K> {
+        fn collect<K: Copy, V>(node: &Node<K, V>, keys: &mut Vec<K>) {
+            match *node {
+                Node::Leaf(ref leaf) => keys.push(leaf.key),
+                Node::Internal(ref internal) => {
+                    for child in &internal.nybbles.data {
+                        collect(child, keys);
+                    }
+                }
+            }
+        }
+
+        let mut keys = Vec::new();
+        if let Some(ref root) = trie.root {
+            collect(root, &mut keys);
+        }
+        keys
+    }
+
+
+    fn trie_of<K: AsRef<[u8]> + AsMut<[u8]> + Copy>(keys: &[impl AsRef<[u8]>]) -> Trie<K, ()> {
+        let mut trie = Trie::new();
+        for key in keys {
+            trie.insert(key, ());
+        }
+        trie
+    }
+
+
+    // A repeatable stream of pseudorandom bytes, drawn from a linear congruential generator.
+    fn random_bytes(seed: u32) -> impl FnMut() -> u8 {
+        let mut state = seed;
+        move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as u8
+        }
+    }
+
+
+    #[test]
+    fn it_works() {}
+
+
+    #[test]
+    fn get_two_mut() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        for (i, key) in [[1, 1], [1, 2], [2, 1]].iter().enumerate() {
+            trie.insert(key, i as u8);
+        }
+
+        {
+            let (a, b) = trie.get_two_mut([2, 1], [1, 1]).unwrap();
+            mem::swap(a, b);
+        }
+        {
+            let (a, b) = trie.get_two_mut([1, 1], [1, 2]).unwrap();
+            *b += *a;
+        }
+        assert_eq!(trie.get_or_panic([1, 1]), &2);
+        assert_eq!(trie.get_or_panic([1, 2]), &3);
+        assert_eq!(trie.get_or_panic([2, 1]), &0);
+
+        assert!(trie.get_two_mut([1, 1], [1, 3]).is_none());
+        assert!(trie.get_two_mut([3, 3], [1, 1]).is_none());
+    }
+
+
+    #[test]
+    #[should_panic(expected = "cannot borrow the value for key [0] mutably twice")]
+    fn get_two_mut_same_key() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 0);
+        trie.get_two_mut([0], [0]);
+    }
+
+
+    #[test]
+    fn insert_returning_position() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        assert_eq!(trie.insert_returning_position(b"c", 0), (None, 0));
+        assert_eq!(trie.insert_returning_position(b"a", 1), (None, 0));
+        assert_eq!(trie.insert_returning_position(b"b", 2), (None, 1));
+        assert_eq!(trie.insert_returning_position(b"c", 3), (Some(0), 2));
+    }
+
+
+    #[test]
+    fn insert_returning_position_counted() {
+        let mut trie = Trie::<[u8; 2], usize>::new();
+        let mut sorted = Vec::new();
+
+        let inserted = [b"mm", b"ab", b"zz", b"ma", b"mz", b"ac", b"m0", b"ab", b"mb", b"aa"];
+        for (i, &key) in inserted.iter().enumerate() {
+            if i % 2 == 0 {
+                trie.build_prefix_count_cache();
+            }
+
+            let (_, position) = trie.insert_returning_position(key, i);
+            if let Err(at) = sorted.binary_search(key) {
+                sorted.insert(at, *key);
+            }
+            assert_eq!(position, sorted.binary_search(key).unwrap());
+        }
+        assert_eq!(keys(&trie), sorted);
+    }
+
+
+    #[test]
+    fn saturating_insert() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        assert!(trie.saturating_insert([0], 0, 2));
+        assert!(trie.saturating_insert([1], 1, 2));
+        assert!(!trie.saturating_insert([2], 2, 2));
+        assert!(!trie.saturating_insert([0], 3, 2));
+
+        assert_eq!(keys(&trie), vec![[0], [1]]);
+        assert_eq!(trie.get_or_panic([0]), &0);
+    }
+
+
+    #[test]
+    fn extend_from_slice() {
+        let mut trie = Trie::<[u8; 2], String>::new();
+        trie.insert(b"ab", "old".to_string());
+        trie.insert(b"zz", "kept".to_string());
+
+        let pairs = [(b"ab", "new".to_string()),
+                     (b"aa", "a".to_string()),
+                     (b"aa", "b".to_string())];
+        trie.extend_from_slice(&pairs);
+        trie.extend_from_slice::<&[u8]>(&[]);
+
+        // The slice is left untouched, and later pairs win over earlier ones and the trie.
+        assert_eq!(pairs[0].1, "new");
+        assert_eq!(trie.len(), 3);
+        assert_eq!(trie.get_or_panic(b"aa"), "b");
+        assert_eq!(trie.get_or_panic(b"ab"), "new");
+        assert_eq!(trie.get_or_panic(b"zz"), "kept");
+    }
+
+
+    #[test]
+    fn insert_many() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert_many::<0, &[u8]>([]);
+        assert!(trie.is_empty());
+
+        trie.insert(b"ab", 0);
+        trie.insert_many([(b"ab", 1), (b"ba", 2), (b"aa", 3), (b"ba", 4)]);
+
+        assert_eq!(trie.len(), 3);
+        assert_eq!(*trie.get_or_panic(b"aa"), 3);
+        assert_eq!(*trie.get_or_panic(b"ab"), 1);
+        assert_eq!(*trie.get_or_panic(b"ba"), 4);
+    }
+
+
+    #[test]
+    fn insert_replaces_existing() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+
+        assert_eq!(trie.insert(b"ab", 1), None);
+        assert_eq!(trie.insert(b"ac", 2), None);
+        assert_eq!(trie.insert(b"ba", 3), None);
+        assert_eq!(trie.insert(b"ac", 4), Some(2));
+        assert_eq!(trie.insert(b"ab", 5), Some(1));
+        assert_eq!(trie.insert(b"ba", 6), Some(3));
+    }
+
+
+    #[test]
+    fn dedup_by_key() {
+        let mut trie = trie_of::<[u8; 2]>(&[b"Ab", b"ab", b"aB", b"aC", b"bc", b"xb"]);
+
+        assert_eq!(trie.dedup_by_key(|key| &key[1..]), 2);
+        assert_eq!(keys(&trie), vec![*b"Ab", *b"aB", *b"aC", *b"bc"]);
+    }
+
+
+    #[test]
+    fn merge_sorted_slices() {
+        let tries = [&[(0, 'a'), (3, 'a')][..], &[(1, 'b'), (3, 'b')], &[], &[(2, 'c'), (3, 'c')]]
+            .iter()
+            .map(|entries| {
+                     let mut trie = Trie::<[u8; 1], char>::new();
+                     for &(key, val) in entries.iter() {
+                         trie.insert([key], val);
+                     }
+                     trie
+                 })
+            .collect::<Vec<_>>();
+
+        let merged = Trie::merge_sorted_slices(tries);
+        assert_eq!(merged.iter().map(|(key, val)| (key[0], *val)).collect::<Vec<_>>(),
+                   vec![(0, 'a'), (1, 'b'), (2, 'c'), (3, 'c')]);
+    }
+
+
+    #[test]
+    fn union_into() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 0);
+        trie.insert([1], 1);
+
+        let mut other = Trie::new();
+        other.insert([1], 10);
+        other.insert([2], 20);
+
+        trie.union_into(other);
+        assert_eq!(keys(&trie), vec![[0], [1], [2]]);
+        assert_eq!(trie.get_or_panic([1]), &1);
+        assert_eq!(trie.get_or_panic([2]), &20);
+    }
+
+
+    #[test]
+    fn difference_in_place() {
+        let mut left = Trie::<[u8; 2], u32>::new();
+        left.insert(b"ab", 1);
+        left.insert(b"ac", 2);
+        left.insert(b"ba", 3);
+
+        let mut right = Trie::<[u8; 2], u32>::new();
+        right.insert(b"ac", 4);
+        right.insert(b"bb", 5);
+
+        left.difference_in_place(&right);
+        assert_eq!(keys(&left), vec![*b"ab", *b"ba"]);
+        assert_eq!(keys(&right), vec![*b"ac", *b"bb"]);
+    }
+
+
+    #[test]
+    fn difference_in_place_empty() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert([1, 2], 1);
+
+        trie.difference_in_place(&Trie::new());
+        assert_eq!(trie.len(), 1);
+        assert_eq!(keys(&trie), vec![[1, 2]]);
+
+        let mut empty = Trie::<[u8; 2], u32>::new();
+        empty.difference_in_place(&trie);
+        assert!(empty.is_empty());
+        assert_eq!(empty.iter().count(), 0);
+    }
+
+
+    #[test]
+    fn difference_in_place_matches_removal() {
+        let mut next = random_bytes(5);
+
+        for round in 0..60 {
+            let mut left = Trie::<[u8; 2], ()>::new();
+            let mut right = Trie::<[u8; 2], ()>::new();
+            for _ in 0..round {
+                left.insert([next() & 0x53, next() & 0x1c], ());
+                right.insert([next() & 0x53, next() & 0x1c], ());
+            }
+
+            let mut expected = Trie::<[u8; 2], ()>::new();
+            for (key, _) in left.iter() {
+                expected.insert(key, ());
+            }
+            for (key, _) in right.iter() {
+                expected.try_remove(key).ok();
+            }
+
+            left.difference_in_place(&right);
+            assert!(left.compare_structure(&expected));
+            assert_eq!(left.len(), expected.len());
+            assert_eq!(left.iter().len(), left.iter().count());
+        }
+    }
+
+
+    #[test]
+    fn entry_for_bytes() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+
+        *trie.entry_for_bytes(b"ab").or_insert(0) += 1;
+        *trie.entry_for_bytes(b"ab").or_insert(0) += 1;
+        *trie.entry_for_bytes(b"ac").or_insert_with(|| 5) += 1;
+
+        match trie.entry_for_bytes(b"ab") {
+            Entry::Occupied(mut entry) => {
+                assert_eq!(entry.key(), b"ab");
+                assert_eq!(entry.insert(7), 2);
+            }
+            Entry::Vacant(..) => panic!("expected an occupied entry"),
+        }
+
+        match trie.entry_for_bytes(b"ba") {
+            Entry::Occupied(..) => panic!("expected a vacant entry"),
+            Entry::Vacant(entry) => assert_eq!(entry.key(), b"ba"),
+        }
+
+        assert_eq!(trie.try_remove(b"ab"), Ok(7));
+        assert_eq!(trie.try_remove(b"ac"), Ok(6));
+        assert_eq!(trie.try_remove(b"ba"), Err(KeyNotFound));
+    }
+
+
+    #[test]
+    fn find() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        trie.insert(b"c", 1);
+        trie.insert(b"a", 3);
+        trie.insert(b"b", 2);
+
+        assert_eq!(trie.find(|_, val| *val < 3), Some((b"b", &2)));
+        assert_eq!(trie.find(|_, val| *val > 3), None);
+    }
+
+
+    #[test]
+    fn flip_prefix() {
+        let mut trie = Trie::<[u8; 3], u32>::new();
+        trie.insert(b"abc", 1);
+        trie.insert(b"abd", 2);
+        trie.insert(b"acd", 3);
+        trie.insert(b"xyd", 4);
+
+        assert_eq!(trie.flip_prefix(b"ab", b"xy"), 2);
+        assert_eq!(keys(&trie), vec![*b"acd", *b"xyc", *b"xyd"]);
+        assert_eq!(trie.try_remove(b"xyd"), Ok(2));
+
+        assert_eq!(trie.flip_prefix(b"ab", b"zz"), 0);
+        assert_eq!(trie.flip_prefix(b"", b""), 2);
+        assert_eq!(keys(&trie), vec![*b"acd", *b"xyc"]);
+    }
+
+
+    #[test]
+    fn move_prefix() {
+        let mut trie = Trie::<[u8; 3], u32>::new();
+        trie.insert(b"abc", 1);
+        trie.insert(b"abd", 2);
+        trie.insert(b"xyd", 3);
+
+        assert_eq!(trie.move_prefix(b"ab", b"xy"), 2);
+        assert_eq!(keys(&trie), vec![*b"xyc", *b"xyd"]);
+        assert_eq!(trie.get_or_panic(b"xyd"), &2);
+        assert_eq!(trie.move_prefix(b"ab", b"zz"), 0);
+
+        let moved = panic::catch_unwind(|| trie.clone_prefix(b"").move_prefix(b"x", b"ab"));
+        assert!(moved.is_err());
+    }
+
+
+    #[test]
+    fn from_fn() {
+        let trie = Trie::<[u8; 2], usize>::from_fn(|_| None);
+        assert!(trie.is_empty());
+
+        // Later entries replace earlier ones with the same key, as with `insert`, and `f` is not
+        // called again once it returns `None`.
+        let mut calls = Vec::new();
+        let trie = Trie::<[u8; 2], usize>::from_fn(|i| {
+                                                       calls.push(i);
+                                                       let key = [b'a', b'a' + (i % 3) as u8];
+                                                       if i < 4 { Some((key, i)) } else { None }
+                                                   });
+        assert_eq!(calls, [0, 1, 2, 3, 4]);
+        assert_eq!(trie.len(), 3);
+        assert_eq!(*trie.get_or_panic(b"aa"), 3);
+        assert_eq!(*trie.get_or_panic(b"ab"), 1);
+        assert_eq!(*trie.get_or_panic(b"ac"), 2);
+    }
+
+
+    #[test]
+    fn from_parts() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+
+        let parts = trie.into_parts();
+        assert_eq!(parts.len, 2);
+        match parts.root {
+            Some(Node::Internal(ref internal)) => {
+                // `ab` and `ac` first differ in the low nybble of their second byte.
+                assert_eq!(internal.index(), 3);
+                assert!(internal.child(1).is_none());
+                match (internal.child(2), internal.child(3)) {
+                    (Some(Node::Leaf(ab)), Some(Node::Leaf(ac))) => {
+                        assert_eq!((ab.key(), ab.val()), (b"ab", &1));
+                        assert_eq!((ac.key(), ac.val()), (b"ac", &2));
+                    }
+                    _ => panic!("expected leaves under the nybbles 2 and 3"),
+                }
+                assert_eq!(internal.children().count(), 2);
+            }
+            _ => panic!("expected the root to branch"),
+        }
+
+        let mut trie = unsafe { Trie::from_parts(parts) };
+        assert_eq!(trie.len(), 2);
+        assert_eq!(trie.try_remove(b"ac"), Ok(2));
+        assert!(matches!(trie.into_parts().root, Some(Node::Leaf(..))));
+    }
+
+
+    #[test]
+    fn from_prefix_map() {
+        let mut map = BTreeMap::new();
+        map.insert(b"ba".to_vec(), 1);
+        map.insert(b"ab".to_vec(), 2);
+
+        let trie = Trie::<[u8; 2], u32>::from_prefix_map(map);
+        assert_eq!(trie.get_or_panic(b"ab"), &2);
+        assert_eq!(trie.get_or_panic(b"ba"), &1);
+    }
+
+
+    #[test]
+    fn get_at_depth() {
+        let trie = trie_of::<[u8; 2]>(&[[0x10, 0], [0x21, 0], [0x22, 0], [0x30, 0]]);
+
+        let at = |depth| trie.get_at_depth(depth).map(|(key, _)| *key).collect::<Vec<_>>();
+        assert!(at(0).is_empty());
+        assert_eq!(at(1), vec![[0x10, 0], [0x30, 0]]);
+        assert_eq!(at(2), vec![[0x21, 0], [0x22, 0]]);
+        assert!(at(3).is_empty());
+    }
+
+
+    #[test]
+    fn get_entry_at_nth_position() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        let mut sorted = Vec::new();
+        for i in (0..40u8).rev() {
+            let key = [i % 3 * 0x41, i];
+            trie.insert(key, ());
+            sorted.push(key);
+        }
+        sorted.sort();
+
+        for build in &[false, true] {
+            if *build {
+                trie.build_prefix_count_cache();
+            }
+
+            for (n, key) in sorted.iter().enumerate() {
+                assert_eq!(trie.get_entry_at_nth_position(n), Some((key, &())));
+            }
+            assert_eq!(trie.get_entry_at_nth_position(sorted.len()), None);
+        }
+    }
+
+
+    #[test]
+    fn get_prefix_with_wildcard() {
+        let trie = trie_of::<[u8; 2]>(&[[0x12, 0x00], [0x13, 0x00], [0x22, 0x00], [0x32, 0x01],
+                                            [0x32, 0x10]]);
+
+        let matching = |prefix: &[u8], idx| {
+            trie.get_prefix_with_wildcard(prefix, idx).map(|(key, _)| *key).collect::<Vec<_>>()
+        };
+        assert_eq!(matching(&[0x12], 0),
+                   vec![[0x12, 0x00], [0x22, 0x00], [0x32, 0x01], [0x32, 0x10]]);
+        assert_eq!(matching(&[0x12], 1), vec![[0x12, 0x00], [0x13, 0x00]]);
+        assert_eq!(matching(&[0x32, 0x00], 3), vec![[0x32, 0x01]]);
+        assert_eq!(matching(&[0x12], 2), vec![[0x12, 0x00]]);
+        assert!(matching(&[0x42, 0x00], 1).is_empty());
+    }
+
+
+    #[test]
+    fn update_or_remove() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        trie.insert(b"a", 1);
+        trie.insert(b"b", 2);
+
+        assert!(trie.update_or_remove(b"a", |val| Some(val + 10)));
+        assert!(trie.update_or_remove(b"b", |_| None));
+        assert!(!trie.update_or_remove(b"c", |_| panic!("called for a missing key")));
+
+        assert_eq!(trie.len(), 1);
+        assert_eq!(keys(&trie), vec![*b"a"]);
+        assert_eq!(*trie.get_or_panic(b"a"), 11);
+    }
+
+
+    #[test]
+    fn update_or_remove_in_place() {
+        let mut trie = Trie::<[u8; 2], String>::new();
+        for key in [b"aa", b"ab", b"ba"] {
+            trie.insert(key, String::from_utf8(key.to_vec()).unwrap());
+        }
+
+        // Updating a value leaves the structure, and so the count cache, as it was.
+        trie.build_prefix_count_cache();
+        assert!(trie.update_or_remove(b"ab", |val| Some(val + "!")));
+        assert!(trie.counts_valid);
+        assert_eq!(trie.get_or_panic(b"ab"), "ab!");
+        assert_eq!(trie.len(), 3);
+
+        assert!(trie.update_or_remove(b"aa", |_| None));
+        assert_eq!(keys(&trie), vec![*b"ab", *b"ba"]);
+        assert_eq!(trie.len(), 2);
+
+        // A panic in `f` loses the entry, whose value `f` owned, but leaves the rest intact.
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            trie.update_or_remove(b"ba", |_| panic!("update panicked"));
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(keys(&trie), vec![*b"ab"]);
+        assert_eq!(trie.len(), 1);
+        assert_eq!(trie.get_or_panic(b"ab"), "ab!");
+    }
+
+
+    #[test]
+    fn longest_prefix_match_value() {
+        let mut trie = Trie::<[u8; 2], &str>::new();
+        trie.insert(b"ab", "ab");
+        trie.insert(b"ac", "ac");
+
+        assert_eq!(trie.longest_prefix_match_value(b"ab"), Some(&"ab"));
+        assert_eq!(trie.longest_prefix_match_value(b"acd"), Some(&"ac"));
+        assert_eq!(trie.longest_prefix_match_value(b"ad"), None);
+        assert_eq!(trie.longest_prefix_match_value(b"a"), None);
+    }
+
+
+    #[test]
+    fn contains_all_of_slice() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        trie.insert(b"a", ());
+        trie.insert(b"b", ());
+
+        assert!(trie.contains_all_of_slice(&[b"a", b"b"]));
+        assert!(!trie.contains_all_of_slice(&[b"a", b"c"]));
+        assert!(trie.contains_all_of_slice::<&[u8]>(&[]));
+    }
+
+
+    #[test]
+    fn contains_all_of_slice_sorted_walk() {
+        let trie = trie_of::<[u8; 2]>(&[[0x12, 0x34], [0x12, 0x35], [0x13, 0x00], [0x52, 0x00]]);
+
+        assert!(trie.contains_all_of_slice(&[[0x12, 0x34], [0x12, 0x35], [0x52, 0x00]]));
+        assert!(trie.contains_all_of_slice(&[[0x52, 0x00], [0x12, 0x34], [0x52, 0x00]]));
+        assert!(trie.contains_all_of_slice(&keys(&trie)));
+
+        // Keys missing beside, between, before and after the stored ones.
+        assert!(!trie.contains_all_of_slice(&[[0x12, 0x34], [0x12, 0x36]]));
+        assert!(!trie.contains_all_of_slice(&[[0x12, 0x34], [0x14, 0x00]]));
+        assert!(!trie.contains_all_of_slice(&[[0x00, 0x00], [0x12, 0x34]]));
+        assert!(!trie.contains_all_of_slice(&[[0x52, 0x00], [0xff, 0x00]]));
+        assert!(!trie.contains_all_of_slice(&[[0x22, 0x00]]));
+
+        assert!(!Trie::<[u8; 2], ()>::new().contains_all_of_slice(&[[0x12, 0x34]]));
+        assert!(Trie::<[u8; 2], ()>::new().contains_all_of_slice::<[u8; 2]>(&[]));
+    }
+
+
+    #[test]
+    fn merge_prefix() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"bb", 1);
+
+        let mut other = Trie::<[u8; 2], u32>::new();
+        other.insert(b"ab", 2);
+        other.insert(b"ac", 2);
+        other.insert(b"bc", 2);
+
+        trie.merge_prefix(&other, b"a");
+        assert_eq!(keys(&trie), vec![*b"ab", *b"ac", *b"bb"]);
+        assert_eq!(*trie.get_or_panic(b"ab"), 2);
+        assert_eq!(*trie.get_or_panic(b"bb"), 1);
+
+        trie.merge_prefix(&other, b"z");
+        assert_eq!(trie.len(), 3);
+    }
+
+
+    #[test]
+    fn extend_from_trie() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"aa", 0);
+        trie.insert(b"ab", 0);
+
+        let mut other = Trie::<[u8; 2], u32>::new();
+        other.insert(b"ab", 1);
+        other.insert(b"ba", 2);
+
+        trie.extend_from_trie(&other);
+        trie.extend_from_trie(&Trie::new());
+
+        // `other` keeps its entries, and its values replace those already in the trie.
+        assert_eq!(keys(&other), [*b"ab", *b"ba"]);
+        assert_eq!(trie.len(), 3);
+        assert_eq!(*trie.get_or_panic(b"aa"), 0);
+        assert_eq!(*trie.get_or_panic(b"ab"), 1);
+        assert_eq!(*trie.get_or_panic(b"ba"), 2);
+
+        let mut empty = Trie::<[u8; 2], u32>::new();
+        empty.extend_from_trie(&other);
+        assert_eq!(keys(&empty), keys(&other));
+    }
+
+
+    #[test]
+    fn get_adjacent() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.get_adjacent(b"ab"), (None, None));
+
+        let mut next = random_bytes(7);
+
+        for _ in 0..200 {
+            let key = [next() & 0x35, next() & 0x73];
+            trie.insert(key, ());
+
+            let queries: [&[u8]; 5] = [&[next() & 0x35, next() & 0x73],
+                                       &key,
+                                       &key[..1],
+                                       &[key[0], key[1], next()],
+                                       &[]];
+            for query in &queries {
+                let expected_before = trie.iter().rev().find(|&(k, _)| k.as_ref() < *query);
+                let expected_after = trie.iter().find(|&(k, _)| k.as_ref() > *query);
+                assert_eq!(trie.get_adjacent(query), (expected_before, expected_after));
+            }
+        }
+    }
+
+
+    #[test]
+    fn coalesce() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        trie.insert(b"ab", ());
+        trie.insert(b"ac", ());
+        trie.insert(b"bd", ());
+        let expected = trie.clone_prefix(b"");
+
+        // Wrap the root, and one of its children, in internal nodes with a single child each.
+        let mut root = trie.root.take().unwrap();
+        {
+            let child = &mut root.mut_unwrap_internal().nybbles.data[0];
+            let mut wrapper = Internal::new(2);
+            wrapper.nybbles.insert_fresh(6, mem::replace(child, Node::Internal(Internal::new(0))));
+            *child = Node::Internal(wrapper);
+        }
+        let mut wrapper = Internal::new(0);
+        wrapper.nybbles.insert_fresh(6, root);
+        trie.root = Some(Node::Internal(wrapper));
+        assert!(!trie.compare_structure(&expected));
+
+        trie.coalesce();
+        assert!(trie.compare_structure(&expected));
+        assert_eq!(keys(&trie), vec![*b"ab", *b"ac", *b"bd"]);
+    }
+
+
+    #[test]
+    fn to_compact_string() {
+        let trie = trie_of::<[u8; 2]>(&[b"ab", b"ac", b"bd", b"ce", b"cf", b"cg", b"dh", b"ei",
+                                            b"fj"]);
+
+        assert_eq!(trie.to_compact_string(),
+                   "entries: 9\n\
+                    height: 2\n\
+                    branching factor: 3.67\n\
+                    top prefixes:\n  \
+                    63: 3\n  \
+                    61: 2\n  \
+                    62: 1\n  \
+                    64: 1\n  \
+                    65: 1\n");
+    }
+
+
+    #[test]
+    fn for_each_depth_first() {
+        let mut visited = Vec::new();
+        Trie::<[u8; 2], ()>::new().for_each_depth_first(|key, _| visited.push(*key));
+        assert!(visited.is_empty());
+
+        // Each key differs from the all-zero key in one more trailing byte, so the trie is a
+        // single path as deep as the keys are long.
+        let mut trie = Trie::<[u8; 64], usize>::new();
+        for i in 0..64 {
+            let mut key = [0; 64];
+            key[i] = 1;
+            trie.insert(&key[..], i);
+        }
+        trie.insert(&[0; 64][..], 64);
+
+        let mut visited = Vec::new();
+        trie.for_each_depth_first(|key, &val| visited.push((*key, val)));
+        assert_eq!(visited, trie.iter().map(|(key, &val)| (*key, val)).collect::<Vec<_>>());
+        assert_eq!(visited.first().map(|&(_, val)| val), Some(64));
+        assert_eq!(visited.last().map(|&(_, val)| val), Some(0));
+    }
+
+
+    #[test]
+    fn depth_first_mut() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        for key in &[b"aa", b"ab", b"ac", b"ba", b"ca", b"cb", b"da"] {
+            trie.insert(key, 0);
+        }
+
+        let mut visited = Vec::new();
+        trie.depth_first_mut(|key, val| {
+            visited.push(*key);
+            *val += 1;
+            match key {
+                b"aa" | b"ca" => DepthFirstControl::SkipSubtrie,
+                b"ba" => DepthFirstControl::Continue,
+                _ => DepthFirstControl::Stop,
+            }
+        });
+
+        assert_eq!(visited, vec![*b"aa", *b"ba", *b"ca", *b"da"]);
+        assert_eq!(trie.iter().map(|(_, &val)| val).collect::<Vec<_>>(),
+                   vec![1, 0, 0, 1, 1, 0, 1]);
+    }
+
+
+    #[test]
+    fn multi_get() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+        trie.insert(b"bd", 3);
+
+        assert_eq!(trie.multi_get([b"ab", b"ac", b"ad"]), [Some(&1), Some(&2), None]);
+        assert_eq!(trie.multi_get([b"bd", b"ab"]), [Some(&3), Some(&1)]);
+        assert_eq!(trie.multi_get([b"cd", b"ce"]), [None, None]);
+        assert_eq!(trie.multi_get([b"ab", b"a"]), [Some(&1), None]);
+        assert_eq!(trie.multi_get([]), []);
+    }
+
+
+    #[test]
+    fn capacity() {
+        // Sum the allocated child slots of every internal node, walking the tree by hand.
+        fn allocated<K, V>(trie: &Trie<K, V>) -> usize {
+            let mut total = 0;
+            let mut stack: Vec<&Node<K, V>> = trie.root.iter().collect();
+            while let Some(node) = stack.pop() {
+                if let Node::Internal(internal) = node {
+                    total += internal.nybbles.data.capacity();
+                    stack.extend(internal.nybbles.data.iter());
+                }
+            }
+            total
+        }
+
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.capacity(), 0);
+        trie.insert(b"ab", ());
+        assert_eq!(trie.capacity(), 0);
+
+        for key in [b"ac", b"ad", b"bd", b"be"] {
+            trie.insert(key, ());
+        }
+        let (_, edges) = trie.root.as_ref().unwrap().count_internal();
+        assert!(trie.capacity() >= edges);
+        assert_eq!(trie.capacity(), allocated(&trie));
+
+        // The node under `a` now has room for all sixteen children, three of them in use.
+        trie.reserve_prefix(b"a", 100);
+        assert!(trie.capacity() >= edges + 13);
+        assert_eq!(trie.capacity(), allocated(&trie));
+
+        trie.try_remove(b"ad").unwrap();
+        assert_eq!(trie.capacity(), allocated(&trie));
+        trie.try_remove(b"bd").unwrap();
+        assert_eq!(trie.capacity(), allocated(&trie));
+        trie.try_remove(b"ab").unwrap();
+        trie.try_remove(b"ac").unwrap();
+        assert_eq!(trie.capacity(), allocated(&trie));
+        trie.try_remove(b"be").unwrap();
+        assert_eq!(trie.capacity(), 0);
+    }
+
+
+    #[test]
+    fn reserve_prefix() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        trie.insert(b"ab", ());
+        trie.insert(b"ac", ());
+        trie.insert(b"bd", ());
+
+        trie.reserve_prefix(b"a", 100);
+        match trie.root.as_ref().and_then(|root| root.get_prefix(b"a")) {
+            Some(Node::Internal(internal)) => assert!(internal.nybbles.data.capacity() >= 16),
+            _ => panic!("expected the keys starting with `a` to branch"),
+        }
+        trie.reserve_prefix(b"bd", 100);
+        trie.reserve_prefix(b"c", 100);
+
+        for b in 0..16 {
+            trie.insert([b'a', 0x60 | b], ());
+        }
+        assert_eq!(trie.len(), 17);
+    }
+
+
+    #[test]
+    fn reserve_prefix_subtrie() {
+        let mut trie = trie_of::<[u8; 3]>(&[b"aab", b"aac", b"abd", b"abe", b"bcd"]);
+        let capacities = |trie: &Trie<[u8; 3], ()>, prefix: &[u8]| {
+            let mut capacities = Vec::new();
+            let mut stack: Vec<&Node<[u8; 3], ()>> = trie.root
+                .as_ref()
+                .and_then(|root| root.get_prefix(prefix))
+                .into_iter()
+                .collect();
+            while let Some(node) = stack.pop() {
+                if let Node::Internal(internal) = node {
+                    capacities.push(internal.nybbles.data.capacity());
+                    stack.extend(internal.nybbles.data.iter());
+                }
+            }
+            capacities
+        };
+        let root_capacity = capacities(&trie, b"")[0];
+
+        // Every internal node under `a` has two children, and room for three more.
+        trie.reserve_prefix(b"a", 3);
+        let under_a = capacities(&trie, b"a");
+        assert_eq!(under_a.len(), 3);
+        assert!(under_a.iter().all(|&capacity| capacity >= 5));
+        assert_eq!(capacities(&trie, b"")[0], root_capacity);
+    }
+
+
+    #[test]
+    fn into_flattened_string_map() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"cd", 2);
+
+        let map = trie.into_flattened_string_map().unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["ab"], 1);
+        assert_eq!(map["cd"], 2);
+
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert([0xff, 0xfe], 2);
+        assert!(trie.into_flattened_string_map().is_err());
+    }
+
+
+    #[test]
+    fn prefix_histogram() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert!(trie.prefix_histogram().is_empty());
+        trie.insert(b"abc", ());
+        assert!(trie.prefix_histogram().is_empty());
+
+        trie.insert(b"abd", ());
+        trie.insert(b"aef", ());
+        trie.insert(b"ghi", ());
+        assert_eq!(trie.prefix_histogram(), vec![4, 3, 2]);
+    }
+
+
+    #[test]
+    fn get_unchecked() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+        trie.insert(b"bd", 3);
+
+        for (key, val) in &[(b"ab", 1), (b"ac", 2), (b"bd", 3)] {
+            assert_eq!(unsafe { trie.get_unchecked(key) }, val);
+        }
+    }
+
+
+    #[test]
+    fn get_many_or_default() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+
+        assert_eq!(trie.get_many_or_default([b"ac", b"zz", b"ab", b""], &0),
+                   [&2, &0, &1, &0]);
+    }
+
+
+    #[test]
+    #[should_panic(expected = "key [97, 100] not found in trie")]
+    fn get_or_panic() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+
+        assert_eq!(trie.get_or_panic(b"ac"), &2);
+        trie.get_or_panic(b"ad");
+    }
+
+
+    #[test]
+    fn get_or_default() {
+        let mut trie = Trie::<[u8; 2], String>::new();
+        trie.insert(b"ab", "x".to_string());
+
+        assert!(matches!(trie.get_or_default(b"ab"), Cow::Borrowed(val) if val == "x"));
+        assert!(matches!(trie.get_or_default(b"ac"), Cow::Owned(ref val) if val.is_empty()));
+        assert!(matches!(trie.get_or_default(b"a"), Cow::Owned(ref val) if val.is_empty()));
+        assert_eq!(trie.total_key_bytes(), 2);
+    }
+
+
+    #[test]
+    fn default_value_for() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 1);
+
+        assert_eq!(trie.default_value_for([0]), &1);
+        assert_eq!(trie.default_value_for([1]), &0);
+        assert_eq!(keys(&trie), vec![[0], [1]]);
+    }
+
+
+    #[test]
+    fn get_or_compute() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 1);
+
+        assert_eq!(trie.get_or_compute([0], |_| Err(())), Ok(&1));
+        assert_eq!(trie.get_or_compute([1], |_| Err(())), Err(()));
+        assert_eq!(keys(&trie), vec![[0]]);
+
+        assert_eq!(trie.get_or_compute::<_, (), _>([2], |key| Ok(key[0] * 2)), Ok(&4));
+        assert_eq!(keys(&trie), vec![[0], [2]]);
+    }
+
+
+    #[test]
+    fn is_prefix_free() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert!(trie.is_prefix_free());
+
+        trie.insert(b"ab", ());
+        trie.insert(b"ac", ());
+        assert!(trie.is_prefix_free());
+
+        let trie = trie_of::<[u8; 3]>(&[b"aab", b"aac", b"abd", b"bcd"]);
+        assert!(trie.is_prefix_free());
+    }
+
+
+    #[test]
+    fn iter() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        assert!(trie.is_empty());
+        assert_eq!(trie.iter().next(), None);
+
+        for key in b"dbeac" {
+            trie.insert([*key], *key);
+        }
+        assert_eq!(trie.len(), 5);
+
+        let vals = trie.iter().map(|(_, val)| *val).collect::<Vec<_>>();
+        assert_eq!(vals, b"abcde");
+
+        let vals = trie.iter_in_reverse().map(|(_, val)| *val).collect::<Vec<_>>();
+        assert_eq!(vals, b"edcba");
+
+        let mut iter = trie.iter();
+        assert_eq!(iter.next(), Some((b"a", &b'a')));
+        assert_eq!(iter.next_back(), Some((b"e", &b'e')));
+        assert_eq!(iter.len(), 3);
+        assert_eq!(iter.map(|(_, val)| *val).collect::<Vec<_>>(), b"bcd");
+    }
+
+
+    #[test]
+    fn into_keys_and_values() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        for key in b"cab" {
+            trie.insert([*key], *key - b'a');
+        }
+
+        let keys = trie.clone_prefix([]).into_keys();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys.collect::<Vec<_>>(), vec![*b"a", *b"b", *b"c"]);
+        assert_eq!(trie.into_values().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+
+    #[test]
+    fn freeze_into_vec() {
+        assert_eq!(Trie::<[u8; 2], u32>::new().freeze_into_vec(), []);
+
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        for (i, key) in [b"ca", b"ab", b"cb", b"aa", b"ba"].iter().enumerate() {
+            trie.insert(key, i as u32);
+        }
+
+        let frozen = trie.freeze_into_vec();
+        assert_eq!(frozen,
+                   [(*b"aa", 3), (*b"ab", 1), (*b"ba", 4), (*b"ca", 0), (*b"cb", 2)]);
+
+        assert_eq!(frozen.binary_search_by_key(b"ba", |&(key, _)| key), Ok(2));
+        assert_eq!(frozen.binary_search_by_key(b"bb", |&(key, _)| key), Err(3));
+        assert_eq!(frozen.binary_search_by_key(b"00", |&(key, _)| key), Err(0));
+        assert_eq!(frozen.binary_search_by_key(b"zz", |&(key, _)| key), Err(5));
+    }
+
+
+    #[test]
+    fn iter_pairs_between() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ba", b"bb", b"ca"]);
+
+        let between = |start: &[u8], end: &[u8]| {
+            trie.iter_pairs_between(start, end).map(|(key, _)| *key).collect::<Vec<_>>()
+        };
+        assert_eq!(between(b"ab", b"bb"), vec![*b"ab", *b"ba"]);
+        assert_eq!(between(b"a", b"b"), vec![*b"aa", *b"ab"]);
+        assert_eq!(between(b"", b"zz").len(), 5);
+        assert!(between(b"bb", b"ba").is_empty());
+    }
+
+
+    #[test]
+    fn iter_pairs_between_seek() {
+        let mut next = random_bytes(11);
+
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        for _ in 0..150 {
+            trie.insert([next() & 0x35, next() & 0x73], ());
+
+            let start = [next() & 0x35, next() & 0x73];
+            let end = [next() & 0x35, next() & 0x73];
+            for start in &[&start[..], &start[..1], &[start[0], start[1], next()], &[]] {
+                let between = trie.iter_pairs_between(*start, &end[..]).collect::<Vec<_>>();
+                let expected = trie.iter()
+                    .filter(|(key, _)| key.as_ref() >= *start && key.as_ref() < &end[..])
+                    .collect::<Vec<_>>();
+                assert_eq!(between, expected);
+            }
+        }
+    }
+
+
+    #[test]
+    fn iter_chunks() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        assert_eq!(trie.iter_chunks(2).count(), 0);
+
+        for key in b"edcba" {
+            trie.insert([*key], *key);
+        }
+
+        let chunks = trie.iter_chunks(2)
+            .map(|chunk| chunk.into_iter().map(|(_, val)| *val).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(chunks, vec![b"ab".to_vec(), b"cd".to_vec(), b"e".to_vec()]);
+    }
+
+
+    #[test]
+    fn keys_sharing_prefix_with() {
+        let trie = trie_of::<[u8; 2]>(&[[1, 2], [1, 3], [4, 2]]);
+
+        let shared = trie.keys_sharing_prefix_with([1, 2])
+            .map(|(key, _, len)| (*key, len))
+            .collect::<Vec<_>>();
+        assert_eq!(shared, vec![([1, 2], 2), ([1, 3], 1), ([4, 2], 0)]);
+    }
+
+
+    #[test]
+    fn map_entries() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        trie.insert([1, 2], 1);
+        trie.insert([2, 1], 2);
+        trie.insert([3, 1], 3);
+
+        let mapped = trie.map_entries(|[_, b], val| ([b], val as u32 * 10));
+        assert_eq!(keys(&mapped), vec![[1], [2]]);
+        assert_eq!(mapped.get_or_panic([1]), &30);
+        assert_eq!(mapped.get_or_panic([2]), &10);
+    }
+
+
+    #[test]
+    fn map_values_in_place() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+        trie.insert(b"ba", 3);
+
+        trie.map_values_in_place(|key, val| if key[0] == b'a' { val * 10 } else { val });
+        assert_eq!(trie.try_remove(b"ab"), Ok(10));
+        assert_eq!(trie.try_remove(b"ac"), Ok(20));
+        assert_eq!(trie.try_remove(b"ba"), Ok(3));
+    }
+
+
+    #[test]
+    fn map_values_in_place_panic() {
+        let mut trie = Trie::<[u8; 2], String>::new();
+        trie.insert(b"ab", "ab".to_string());
+        trie.insert(b"ac", "ac".to_string());
+
+        let panicked = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            trie.map_values_in_place(|key, val| if key == b"ac" { panic!() } else { val + "!" });
+        }));
+        assert!(panicked.is_err());
+
+        assert_eq!(trie.len(), 0);
+        assert!(trie.is_empty());
+        assert_eq!(trie.iter().len(), 0);
+        assert_eq!(trie.iter().count(), 0);
+
+        trie.insert(b"ab", "ab".to_string());
+        assert_eq!(trie.len(), 1);
+    }
+
+
+    #[test]
+    fn into_prefix_groups() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        for key in b"abcdefg" {
+            trie.insert([*key], ());
+        }
+
+        let groups = trie.into_prefix_groups(3);
+        assert_eq!(groups.iter().map(keys).collect::<Vec<_>>(),
+                   vec![vec![*b"a", *b"b", *b"c"], vec![*b"d", *b"e"], vec![*b"f", *b"g"]]);
+
+        let groups = Trie::<[u8; 1], ()>::new().into_prefix_groups(2);
+        assert_eq!(groups.len(), 2);
+        assert!(groups.iter().all(|group| keys(group).is_empty()));
+    }
+
+
+    #[test]
+    fn split_at_midpoint() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        for key in b"ecadb" {
+            trie.insert([*key], ());
+        }
+
+        let (low, high) = trie.split_at_midpoint();
+        assert_eq!(keys(&low), vec![*b"a", *b"b", *b"c"]);
+        assert_eq!(keys(&high), vec![*b"d", *b"e"]);
+
+        let (low, high) = Trie::<[u8; 1], ()>::new().split_at_midpoint();
+        assert!(low.is_empty() && high.is_empty());
+    }
+
+
+    #[test]
+    fn remove_empty_value_entries() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        trie.insert(b"a", 0);
+        trie.insert(b"b", 2);
+        trie.insert(b"c", 0);
+
+        assert_eq!(trie.remove_empty_value_entries(), 2);
+        assert_eq!(keys(&trie), vec![*b"b"]);
+        assert_eq!(trie.remove_empty_value_entries(), 0);
+    }
+
+
+    #[test]
+    fn path_to_key() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert!(trie.path_to_key([0x12, 0x34]).is_empty());
+
+        trie = trie_of(&[[0x12, 0x34], [0x12, 0x35], [0x22, 0x00]]);
+
+        assert_eq!(trie.path_to_key([0x12, 0x35]), vec![(0, 1, true), (3, 5, true)]);
+        assert_eq!(trie.path_to_key([0x12, 0x36]), vec![(0, 1, true), (3, 6, false)]);
+        assert_eq!(trie.path_to_key([0x32, 0x00]), vec![(0, 3, false)]);
+    }
+
+
+    #[test]
+    fn with_prefix_scope() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ba"]);
+        let scoped = |prefix: &[u8]| {
+            trie.with_prefix_scope(prefix,
+                                   |scope| scope.iter().map(|(key, _)| *key).collect::<Vec<_>>())
+        };
+
+        assert_eq!(scoped(b"a"), Some(vec![*b"aa", *b"ab"]));
+        assert_eq!(scoped(b"ba"), Some(vec![*b"ba"]));
+        assert_eq!(scoped(b""), Some(keys(&trie)));
+        assert_eq!(trie.with_prefix_scope(b"c", |scope| scope.len()), None);
+        assert_eq!(trie.with_prefix_scope(b"a", |scope| scope.len()), Some(2));
+    }
+
+
+    #[test]
+    fn retain_prefix() {
+        let mut trie = trie_of::<[u8; 3]>(&[b"abc", b"abd", b"acd", b"bcd"]);
+
+        trie.retain_prefix(b"a");
+        assert_eq!(keys(&trie), vec![*b"abc", *b"abd", *b"acd"]);
+        trie.retain_prefix(b"ab");
+        assert_eq!(keys(&trie), vec![*b"abc", *b"abd"]);
+        trie.retain_prefix(b"abd");
+        assert_eq!(keys(&trie), vec![*b"abd"]);
+        trie.retain_prefix(b"b");
+        assert_eq!(keys(&trie), Vec::<[u8; 3]>::new());
+    }
+
+
+    #[test]
+    fn try_remove() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+        trie.insert(b"ba", 3);
+
+        assert_eq!(trie.try_remove(b"ac"), Ok(2));
+        assert_eq!(trie.try_remove(b"ac"), Err(KeyNotFound));
+        assert_eq!(trie.try_remove(b"b"), Err(KeyNotFound));
+        assert_eq!(trie.try_remove(b"ba"), Ok(3));
+        assert_eq!(trie.insert(b"ab", 4), Some(1));
+        assert_eq!(trie.try_remove(b"ab"), Ok(4));
+        assert_eq!(trie.try_remove(b"ab"), Err(KeyNotFound));
+        assert_eq!(trie.total_key_bytes(), 0);
+    }
+
+
+    #[test]
+    fn subtrie_height() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.subtrie_height([]), 0);
+
+        trie.insert([0x11, 0x00], ());
+        assert_eq!(trie.subtrie_height([]), 0);
+
+        trie.insert([0x12, 0x00], ());
+        trie.insert([0x12, 0x01], ());
+        trie.insert([0x20, 0x00], ());
+        assert_eq!(trie.subtrie_height([]), 3);
+        assert_eq!(trie.subtrie_height([0x12]), 1);
+        assert_eq!(trie.subtrie_height([0x20]), 0);
+        assert_eq!(trie.subtrie_height([0x30]), 0);
+    }
+
+
+    #[test]
+    fn max_depth_prefix() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.max_depth_prefix(1), None);
+
+        trie = trie_of(&[[1, 0x10], [1, 0x20], [2, 0x11], [2, 0x12], [2, 0x20]]);
+
+        assert_eq!(trie.max_depth_prefix(0), Some(vec![]));
+        assert_eq!(trie.max_depth_prefix(1), Some(vec![2]));
+        assert_eq!(trie.max_depth_prefix(2), Some(vec![1, 0x10]));
+        assert_eq!(trie.max_depth_prefix(3), None);
+    }
+
+
+    #[test]
+    fn take() {
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 0);
+        trie.insert([1], 1);
+
+        assert_eq!(trie.take([1]), Some(([1], 1)));
+        assert_eq!(trie.take([1]), None);
+        assert_eq!(keys(&trie), vec![[0]]);
+    }
+
+
+    #[test]
+    fn swap_out() {
+        let mut trie = trie_of::<[u8; 2]>(&[b"aa", b"ab"]);
+        let replacement = trie_of::<[u8; 2]>(&[b"zz"]);
+
+        let old = trie.swap_out(replacement);
+        assert_eq!(keys(&old), [*b"aa", *b"ab"]);
+        assert_eq!(keys(&trie), [*b"zz"]);
+        assert_eq!(trie.len(), 1);
+
+        let old = trie.swap_out(Trie::new());
+        assert_eq!(keys(&old), [*b"zz"]);
+        assert!(trie.is_empty());
+        assert_eq!(trie.iter().count(), 0);
+    }
+
+
+    #[test]
+    fn remove_bulk() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+        trie.insert(b"ba", 3);
+
+        assert_eq!(trie.remove_bulk(&[b"ab", b"ba", b"ab", b"zz"]), 2);
+        assert_eq!(trie.try_remove(b"ac"), Ok(2));
+        assert_eq!(trie.remove_bulk(vec![b"ac"]), 0);
+    }
+
+
+    #[test]
+    fn remove_bulk_matches_removal() {
+        let mut next = random_bytes(9);
+
+        for round in 0..60 {
+            let mut trie = Trie::<[u8; 2], ()>::new();
+            let mut removals = Vec::new();
+            for _ in 0..round {
+                trie.insert([next() & 0x53, next() & 0x1c], ());
+                removals.push(vec![next() & 0x53, next() & 0x1c]);
+            }
+            removals.push(vec![next() & 0x53]);
+
+            let mut expected = Trie::<[u8; 2], ()>::new();
+            for (key, _) in trie.iter() {
+                expected.insert(key, ());
+            }
+            let present = removals.iter().filter(|key| expected.try_remove(key).is_ok()).count();
+
+            assert_eq!(trie.remove_bulk(&removals), present);
+            assert!(trie.compare_structure(&expected));
+            assert_eq!(trie.len(), expected.len());
+        }
+    }
+
+
+    #[test]
+    fn count_nodes_at_depth() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert_eq!(trie.count_nodes_at_depth(0), (0, 0));
+
+        trie = trie_of(&[b"abc", b"abd", b"abe", b"acd", b"bcd"]);
+
+        assert_eq!(trie.count_nodes_at_depth(0), (1, 0));
+        assert_eq!(trie.count_nodes_at_depth(1), (1, 1));
+        assert_eq!(trie.count_nodes_at_depth(2), (1, 1));
+        assert_eq!(trie.count_nodes_at_depth(3), (0, 3));
+        assert_eq!(trie.count_nodes_at_depth(4), (0, 0));
+    }
+
+
+    #[test]
+    fn count_distinct_prefixes() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert_eq!(trie.count_distinct_prefixes(0), 0);
+
+        trie.insert(b"abc", ());
+        trie.insert(b"abd", ());
+        trie.insert(b"acd", ());
+        trie.insert(b"bcd", ());
+
+        assert_eq!(trie.count_distinct_prefixes(0), 1);
+        assert_eq!(trie.count_distinct_prefixes(1), 2);
+        assert_eq!(trie.count_distinct_prefixes(2), 3);
+        assert_eq!(trie.count_distinct_prefixes(3), 4);
+        assert_eq!(trie.count_distinct_prefixes(4), 0);
+    }
+
+
+    #[test]
+    fn intersection_in_place() {
+        let mut left = Trie::<[u8; 2], u32>::new();
+        left.insert(b"ab", 1);
+        left.insert(b"ac", 2);
+        left.insert(b"ba", 3);
+
+        let mut right = Trie::<[u8; 2], u32>::new();
+        right.insert(b"ac", 4);
+        right.insert(b"ba", 5);
+        right.insert(b"bb", 6);
+
+        left.intersection_in_place(&right);
+        assert_eq!(left.try_remove(b"ac"), Ok(2));
+        assert_eq!(left.try_remove(b"ba"), Ok(3));
+        assert_eq!(keys(&left), Vec::<[u8; 2]>::new());
+    }
+
+
+    #[test]
+    fn validate_sorted_keys() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.validate_sorted_keys(), Ok(()));
+
+        trie = trie_of(&[b"ba", b"ab", b"\xff\x00", b"a\xff", b"\x00\xff", b"aa"]);
+        assert_eq!(trie.validate_sorted_keys(), Ok(()));
+    }
+
+
+    #[test]
+    fn sort_check() {
+        assert!(Trie::<[u8; 2], ()>::new().sort_check());
+        assert!(trie_of::<[u8; 2]>(&[b"ba", b"ab", b"aa", b"bb"]).sort_check());
+
+        // A key type ordered against its bytes is the only way to fail the check.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+        struct Reversed(cmp::Reverse<[u8; 1]>);
+
+        impl AsRef<[u8]> for Reversed {
+            fn as_ref(&self) -> &[u8] {
+                &(self.0).0
+            }
+        }
+
+        impl AsMut<[u8]> for Reversed {
+            fn as_mut(&mut self) -> &mut [u8] {
+                &mut (self.0).0
+            }
+        }
+
+        let trie = trie_of::<Reversed>(&[b"a", b"b"]);
+        let checked = panic::catch_unwind(|| trie.sort_check());
+        if cfg!(debug_assertions) {
+            assert!(checked.is_err());
+        } else {
+            assert_eq!(checked.ok(), Some(false));
+        }
+    }
+
+
+    #[test]
+    fn verify_prefix_ordering() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        let mut next = random_bytes(1);
+
+        for round in 0..400 {
+            let key = [next() & 0x37, next()];
+            match next() % 32 {
+                0 => {
+                    trie.retain_prefix(&key[..1]);
+                }
+                1..=4 => {
+                    trie.flip_prefix(&key[..1], &[next() & 0x37]);
+                }
+                5..=12 => {
+                    trie.try_remove(key).ok();
+                }
+                _ => {
+                    trie.insert(key, round);
+                }
+            }
+
+            assert!(trie.verify_prefix_ordering([]));
+            assert!(trie.verify_prefix_ordering(&key[..1]));
+        }
+    }
+
+
+    #[test]
+    fn max_shared_prefix_depth() {
+        let left = trie_of::<[u8; 2]>(&[[0x12, 0x34], [0x12, 0x35], [0x30, 0x00]]);
+        assert_eq!(left.max_shared_prefix_depth(&trie_of(&[[0x12, 0x36], [0x12, 0x37]])), 3);
+        assert_eq!(left.max_shared_prefix_depth(&trie_of(&[[0x12, 0x46], [0x12, 0x37]])), 0);
+        assert_eq!(left.max_shared_prefix_depth(&trie_of(&[[0x22, 0x36], [0x22, 0x37]])), 0);
+        assert_eq!(left.max_shared_prefix_depth(&trie_of(&[[0x40, 0x00], [0x50, 0x00]])), 0);
+        assert_eq!(left.max_shared_prefix_depth(&Trie::new()), 0);
+    }
+
+
+    #[test]
+    fn count_reachable_nybbles() {
+        let trie = trie_of::<[u8; 2]>(&[[0x12, 0x34], [0x12, 0x35], [0x22, 0x00],
+                                            [0x23, 0x00]]);
+
+        assert_eq!(trie.count_reachable_nybbles(),
+                   vec![1 << 1 | 1 << 2, 1 << 2 | 1 << 3 | 1 << 4 | 1 << 5]);
+    }
+
+
+    #[test]
+    fn export_edges() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert!(trie.export_edges().is_empty());
+
+        trie = trie_of(&[[0x12, 0x34], [0x12, 0x35], [0x22, 0x00]]);
+
+        assert_eq!(trie.export_edges(),
+                   vec![(vec![], vec![0x12], 1),
+                        (vec![0x12], vec![0x12, 0x34], 4),
+                        (vec![0x12], vec![0x12, 0x35], 5),
+                        (vec![], vec![0x22, 0x00], 2)]);
+    }
+
+
+    #[test]
+    fn walk_internal_nodes() {
+        let trie = trie_of::<[u8; 3]>(&[b"abc", b"abd", b"abe", b"acd", b"bcd"]);
+
+        let mut nodes = Vec::new();
+        trie.walk_internal_nodes(|depth, prefix, children| {
+                                     nodes.push((depth, prefix.to_vec(), children));
+                                 });
+
+        assert_eq!(nodes,
+                   vec![(0, vec![], 2),
+                        (1, vec![b'a'], 2),
+                        (2, b"ab".to_vec(), 3)]);
+    }
+
+
+    #[test]
+    fn into_index_map() {
+        let mut trie = Trie::<[u8; 8], char>::new();
+        trie.insert(300u64.to_be_bytes(), 'b');
+        trie.insert(2u64.to_be_bytes(), 'a');
+        trie.insert(u64::MAX.to_be_bytes(), 'c');
+
+        let map = trie.into_index_map();
+        assert_eq!(map.into_iter().collect::<Vec<_>>(),
+                   vec![(2, 'a'), (300, 'b'), (u64::MAX, 'c')]);
+    }
+
+
+    #[test]
+    fn insert_batch_sorted() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ba", 0);
+
+        let mut trie = trie.insert_batch_sorted(vec![(*b"aa", 1), (*b"bb", 2), (*b"bc", 3),
+                                                     (*b"ab", 4), (*b"bc", 5), (*b"ca", 6)]);
+
+        assert_eq!(trie.try_remove(b"aa"), Ok(1));
+        assert_eq!(trie.try_remove(b"ab"), Ok(4));
+        assert_eq!(trie.try_remove(b"ba"), Ok(0));
+        assert_eq!(trie.try_remove(b"bb"), Ok(2));
+        assert_eq!(trie.try_remove(b"bc"), Ok(5));
+        assert_eq!(trie.try_remove(b"ca"), Ok(6));
+        assert_eq!(trie.total_key_bytes(), 0);
+    }
+
+
+    #[test]
+    fn insert_batch_sorted_matches_insert() {
+        let mut next = random_bytes(3);
+
+        for round in 0..50 {
+            let mut expected = Trie::<[u8; 2], u32>::new();
+            for i in 0..round % 7 {
+                expected.insert([next() & 0x73, next() & 0x35], i);
+            }
+            let mut batch = Vec::new();
+            for i in 0..round {
+                batch.push(([next() & 0x73, next() & 0x35], i));
+            }
+            if round % 3 != 0 {
+                batch.sort();
+            }
+
+            let mut trie = Trie::<[u8; 2], u32>::new();
+            for (key, val) in expected.iter() {
+                trie.insert(key, *val);
+            }
+            let trie = trie.insert_batch_sorted(batch.iter().cloned());
+            for &(key, val) in &batch {
+                expected.insert(key, val);
+            }
+
+            assert!(trie.compare_structure(&expected));
+            assert_eq!(trie.len(), expected.len());
+            assert!(trie.iter().eq(expected.iter()));
+        }
+    }
+
+
+    #[test]
+    fn union_map() {
+        let mut left = Trie::<[u8; 2], u64>::new();
+        left.insert(b"ab", 1);
+        left.insert(b"ac", 2);
+
+        let mut right = Trie::<[u8; 2], u32>::new();
+        right.insert(b"ac", 10);
+        right.insert(b"ba", 20);
+
+        let mut union = left.union_map(right, |_, l, r| l + r as u64);
+        assert_eq!(union.try_remove(b"ab"), Ok(1));
+        assert_eq!(union.try_remove(b"ac"), Ok(12));
+        assert_eq!(union.try_remove(b"ba"), Ok(20));
+        assert_eq!(union.total_key_bytes(), 0);
+    }
+
+
+    #[test]
+    fn select_by() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        for (i, key) in b"abcdef".iter().enumerate() {
+            trie.insert([*key], (i as u32 * 7) % 6);
+        }
+
+        assert_eq!(trie.select_by(3, |l, r| l.cmp(r)),
+                   vec![(b"f", &5), (b"e", &4), (b"d", &3)]);
+        assert_eq!(trie.select_by(2, |l, r| r.cmp(l)), vec![(b"a", &0), (b"b", &1)]);
+        assert_eq!(trie.select_by(0, |l, r| l.cmp(r)), vec![]);
+        assert_eq!(trie.select_by(10, |l, r| l.cmp(r)).len(), 6);
+    }
+
+
+    #[test]
+    fn zip_with() {
+        let mut left = Trie::<[u8; 1], u32>::new();
+        left.insert(b"a", 1);
+        left.insert(b"b", 2);
+        left.insert(b"d", 4);
+
+        let mut right = Trie::<[u8; 1], &str>::new();
+        right.insert(b"b", "x");
+        right.insert(b"c", "y");
+        right.insert(b"d", "z");
+
+        let mut zipped = left.zip_with(right, |l, r| format!("{}{}", r, l));
+        assert_eq!(keys(&zipped), vec![*b"b", *b"d"]);
+        assert_eq!(zipped.try_remove(b"b"), Ok("x2".to_string()));
+        assert_eq!(zipped.try_remove(b"d"), Ok("z4".to_string()));
+    }
+
+
+    #[test]
+    fn take_prefix() {
+        let mut trie = trie_of::<[u8; 2]>(&[b"ab", b"ac", b"ba"]);
+
+        assert!(trie.take_prefix(b"c").is_none());
+        assert_eq!(trie.take_prefix(b"a").map(|taken| keys(&taken)),
+                   Some(vec![*b"ab", *b"ac"]));
+        assert!(trie.take_prefix(b"a").is_none());
+        assert_eq!(keys(&trie), vec![*b"ba"]);
+    }
+
+
+    #[test]
+    fn total_key_bytes() {
+        let mut trie = Trie::<[u8; 4], ()>::new();
+        assert_eq!(trie.total_key_bytes(), 0);
+
+        trie.insert(b"abcd", ());
+        trie.insert(b"abce", ());
+        trie.insert(b"abcd", ());
+        trie.insert(b"zzzz", ());
+        assert_eq!(trie.total_key_bytes(), 12);
+    }
+
+
+    #[test]
+    fn min_and_max_key_len() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert_eq!(trie.min_key_len(), None);
+        assert_eq!(trie.max_key_len(), None);
+
+        trie = trie_of(&[b"abc", b"abd", b"bcd"]);
+        assert_eq!(trie.min_key_len(), Some(3));
+        assert_eq!(trie.max_key_len(), Some(3));
+    }
+
+
+    #[test]
+    fn any_and_all() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        assert!(!trie.any(|_, _| true));
+        assert!(trie.all(|_, _| false));
+
+        trie.insert(b"a", 1);
+        trie.insert(b"b", 2);
+        trie.insert(b"c", 3);
+
+        assert!(trie.any(|key, _| key == b"b"));
+        assert!(!trie.any(|_, val| *val > 3));
+        assert!(trie.all(|_, val| *val > 0));
+        assert!(!trie.all(|key, val| key != b"c" && *val < 3));
+    }
+
+
+    #[test]
+    fn count_by() {
+        assert_eq!(Trie::<[u8; 2], u32>::new().count_by(|_, _| true), 0);
+
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        for (i, key) in [b"aa", b"ab", b"ba", b"bb", b"bc"].iter().enumerate() {
+            trie.insert(key, i as u32);
+        }
+
+        assert_eq!(trie.count_by(|_, _| true), trie.len());
+        assert_eq!(trie.count_by(|_, _| false), 0);
+        assert_eq!(trie.count_by(|key, _| key[0] == b'b'), 3);
+        assert_eq!(trie.count_by(|_, &val| val % 2 == 0), 3);
+        assert_eq!(trie.count_by(|key, &val| key[0] == b'a' && val > 0), 1);
+    }
+
+
+    #[test]
+    fn contains_value() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        trie.insert(b"aa", 1);
+        trie.insert(b"ab", 2);
+        trie.insert(b"ba", 3);
+
+        assert!(trie.contains_value(&3));
+        assert!(!trie.contains_value(&4));
+
+        assert!(trie.contains_value_with_prefix(b"a", &2));
+        assert!(!trie.contains_value_with_prefix(b"a", &3));
+        assert!(!trie.contains_value_with_prefix(b"c", &1));
+    }
+
+
+    #[test]
+    fn take_while_prefix() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        for (i, key) in [b"aa", b"ab", b"ac", b"ba"].iter().enumerate() {
+            trie.insert(key, i as u8);
+        }
+
+        let mut seen = Vec::new();
+        assert_eq!(trie.take_while_prefix(b"a", |_, val| {
+                                              seen.push(*val);
+                                              *val < 1
+                                          }),
+                   2);
+        assert_eq!(seen, vec![0, 1]);
+
+        assert_eq!(trie.take_while_prefix(b"a", |_, _| true), 3);
+        assert_eq!(trie.take_while_prefix(b"c", |_, _| true), 0);
+    }
+
+
+    #[test]
+    fn depth_first_search_prefix() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        for (i, key) in [b"aa", b"ab", b"ac", b"ba"].iter().enumerate() {
+            trie.insert(key, i as u8);
+        }
+
+        let mut seen = Vec::new();
+        assert!(!trie.depth_first_search_prefix(b"a", |_, val| {
+            seen.push(*val);
+            if *val == 1 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+        }));
+        assert_eq!(seen, vec![0, 1]);
+
+        assert!(trie.depth_first_search_prefix(b"a", |_, _| ControlFlow::Continue(())));
+        assert!(trie.depth_first_search_prefix(b"c", |_, _| ControlFlow::Break(())));
+    }
+
+
+    #[test]
+    fn apply_to_values() {
+        let mut trie = Trie::<[u8; 1], u32>::new();
+        trie.insert(b"a", 1);
+        trie.insert(b"b", 2);
+        trie.insert(b"c", 3);
+
+        let result = trie.apply_to_values(|key, val| if *val < 2 {
+                                               *val += 10;
+                                               Ok(())
+                                           } else {
+                                               Err(*key)
+                                           });
+
+        assert_eq!(result, Err(*b"b"));
+        assert_eq!(trie.try_remove(b"a"), Ok(11));
+        assert_eq!(trie.try_remove(b"c"), Ok(3));
+    }
+
+
+    #[test]
+    fn branch_factor() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        assert!(trie.branch_factor().is_nan());
+
+        trie.insert(b"a", ());
+        assert!(trie.branch_factor().is_nan());
+
+        for key in b"bcdq" {
+            trie.insert([*key], ());
+        }
+        assert_eq!(trie.branch_factor(), 3.0);
+    }
+
+
+    #[test]
+    fn entry_ratio() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        assert!(trie.entry_ratio().is_nan());
+
+        trie.insert([0x00], ());
+        assert_eq!(trie.entry_ratio(), 1.0);
+
+        trie.insert([0x01], ());
+        trie.insert([0x02], ());
+        trie.insert([0x10], ());
+        assert_eq!(trie.entry_ratio(), 4.0 / 6.0);
+    }
+
+
+    #[test]
+    fn bucket_iter() {
+        let mut trie = Trie::<[u8; 1], ()>::new();
+        for key in 0..10 {
+            trie.insert([key], ());
+        }
+
+        let bucket = |idx| trie.bucket_iter(3, idx).map(|(key, _)| key[0]).collect::<Vec<_>>();
+        assert_eq!(bucket(0), vec![0, 3, 6, 9]);
+        assert_eq!(bucket(1), vec![1, 4, 7]);
+        assert_eq!(bucket(2), vec![2, 5, 8]);
+        assert!(bucket(3).is_empty());
+    }
+
+
+    #[test]
+    fn clone_prefix() {
+        let mut trie = Trie::<[u8; 3], u32>::new();
+        trie.insert(b"abc", 1);
+        trie.insert(b"abd", 2);
+        trie.insert(b"acd", 3);
+
+        let mut cloned = trie.clone_prefix(b"ab");
+        assert_eq!(keys(&cloned), vec![*b"abc", *b"abd"]);
+        assert_eq!(cloned.try_remove(b"abd"), Ok(2));
+        assert_eq!(keys(&trie), vec![*b"abc", *b"abd", *b"acd"]);
+        assert_eq!(keys(&trie.clone_prefix(b"b")), Vec::<[u8; 3]>::new());
+    }
+
+
+    #[test]
+    fn deep_clone_prefix() {
+        let mut trie = Trie::<[u8; 2], Vec<u32>>::new();
+        trie.insert(b"aa", vec![1]);
+        trie.insert(b"ab", vec![2]);
+        trie.insert(b"ba", vec![3]);
+
+        let mut cloned = trie.deep_clone_prefix(b"a");
+        for prefix in [&b""[..], b"a", b"ab", b"c"] {
+            assert_eq!(keys(&trie.deep_clone_prefix(prefix)), keys(&trie.clone_prefix(prefix)));
+        }
+
+        // The values are copies, so changing them leaves the original alone.
+        cloned.get_leaf_mut(b"aa").unwrap().val.push(4);
+        assert_eq!(cloned.get_or_panic(b"aa"), &vec![1, 4]);
+        assert_eq!(trie.get_or_panic(b"aa"), &vec![1]);
+    }
+
+
+    #[test]
+    fn filter_prefix() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"aa", 1);
+        trie.insert(b"ab", 2);
+        trie.insert(b"ba", 3);
+
+        let filtered = trie.filter_prefix(b"a");
+        assert_eq!(keys(&filtered), vec![*b"aa", *b"ab"]);
+        assert_eq!(filtered.len(), 2);
+        assert_eq!(filtered.get_or_panic(b"ab"), &2);
+        assert_eq!(keys(&trie), vec![*b"aa", *b"ab", *b"ba"]);
+
+        for prefix in [&b""[..], b"ba", b"c"] {
+            assert_eq!(keys(&trie.filter_prefix(prefix)), keys(&trie.clone_prefix(prefix)));
+        }
+    }
+
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_prefix() {
+        let trie = trie_of::<[u8; 2]>(&[b"ab", b"ac", b"bc"]);
+
+        let serialize = |prefix: &[u8]| {
+            let mut out = Vec::new();
+            trie.serialize_prefix(prefix, &mut serde_json::Serializer::new(&mut out))
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(serialize(b"a"), "[[[97,98],null],[[97,99],null]]");
+        assert_eq!(serialize(b"bc"), "[[[98,99],null]]");
+        assert_eq!(serialize(b"c"), "[]");
+        assert_eq!(serialize(b""), "[[[97,98],null],[[97,99],null],[[98,99],null]]");
+    }
+
+
+    #[test]
+    fn common_prefix_iter() {
+        let mut left = Trie::<[u8; 1], u8>::new();
+        let mut right = Trie::<[u8; 1], u8>::new();
+        for key in b"abde" {
+            left.insert([*key], 0);
+        }
+        for key in b"bcdf" {
+            right.insert([*key], 1);
+        }
+
+        let common = left.common_prefix_iter(&right)
+            .map(|(key, l, r)| (key[0], *l, *r))
+            .collect::<Vec<_>>();
+        assert_eq!(common, vec![(b'b', 0, 1), (b'd', 0, 1)]);
+    }
+
+
+    #[test]
+    fn clone_without_prefix() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ba"]);
+
+        assert_eq!(keys(&trie.clone_without_prefix(b"a")), vec![*b"ba"]);
+        assert_eq!(keys(&trie.clone_without_prefix(b"c")), vec![*b"aa", *b"ab", *b"ba"]);
+        assert!(trie.clone_without_prefix([]).is_empty());
+        assert_eq!(trie.len(), 3);
+    }
+
+
+    #[test]
+    fn clone_without_prefix_subtries() {
+        let trie = trie_of::<[u8; 3]>(&[b"aab", b"aac", b"abd", b"abe", b"bcd", b"bce"]);
+
+        for prefix in [&b""[..], b"a", b"aa", b"aac", b"ab", b"b", b"bc", b"bcf", b"c"] {
+            let cloned = trie.clone_without_prefix(prefix);
+            let expected = keys(&trie)
+                .into_iter()
+                .filter(|key| !key.starts_with(prefix))
+                .collect::<Vec<_>>();
+
+            assert_eq!(keys(&cloned), expected);
+            assert_eq!(cloned.len(), expected.len());
+
+            // Nodes left with a single child are collapsed, as after a removal.
+            if let Some(ref root) = cloned.root {
+                let (nodes, edges) = root.count_internal();
+                assert!(edges >= 2 * nodes);
+            }
+        }
+    }
+
+
+    #[test]
+    fn compare_structure() {
+        let mut left = Trie::<[u8; 2], u32>::new();
+        let mut right = Trie::<[u8; 2], u32>::new();
+        assert!(left.compare_structure(&right));
+
+        for (i, key) in [b"ab", b"ac", b"ba", b"bb"].iter().enumerate() {
+            left.insert(key, i as u32);
+        }
+        for key in &[b"bb", b"ab", b"ba", b"ac", b"ad"] {
+            right.insert(key, 0);
+        }
+        assert!(!left.compare_structure(&right));
+
+        right.try_remove(b"ad").unwrap();
+        assert!(left.compare_structure(&right));
+
+        right.try_remove(b"ab").unwrap();
+        assert!(!left.compare_structure(&right));
+    }
+
+
+    #[test]
+    fn key_space_coverage() {
+        let trie = trie_of::<[u8; 2]>(&[[0, 0], [0, 1], [1, 0], [2, 0]]);
+
+        assert_eq!(trie.key_space_coverage(0), 1.0);
+        assert_eq!(trie.key_space_coverage(1), 3.0 / 256.0);
+        assert_eq!(trie.key_space_coverage(2), 4.0 / 65536.0);
+    }
+
+
+    #[test]
+    fn compute_optimal_prefix_length() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert_eq!(trie.compute_optimal_prefix_length(4), 0);
+
+        trie = trie_of(&[[0, 0, 0], [0, 1, 0], [0, 1, 1], [1, 0, 0]]);
+
+        assert_eq!(trie.compute_optimal_prefix_length(1), 0);
+        assert_eq!(trie.compute_optimal_prefix_length(2), 1);
+        assert_eq!(trie.compute_optimal_prefix_length(3), 2);
+        assert_eq!(trie.compute_optimal_prefix_length(4), 3);
+        assert_eq!(trie.compute_optimal_prefix_length(5), 3);
+    }
+
+
+    #[test]
+    fn common_prefix_nybbles() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.common_prefix_nybbles(), 0);
+
+        trie.insert([0x12, 0x34], ());
+        assert_eq!(trie.common_prefix_nybbles(), 4);
+
+        trie.insert([0x12, 0x44], ());
+        assert_eq!(trie.common_prefix_nybbles(), 2);
+
+        trie.insert([0x22, 0x44], ());
+        assert_eq!(trie.common_prefix_nybbles(), 0);
+    }
+
+
+    #[test]
+    fn count_empty_key_entries() {
+        let mut trie = Trie::<[u8; 0], u8>::new();
+        assert_eq!(trie.count_empty_key_entries(), 0);
+
+        trie.insert([], 1);
+        trie.insert([], 2);
+        assert_eq!(trie.count_empty_key_entries(), 1);
+
+        let mut trie = Trie::<[u8; 1], u8>::new();
+        trie.insert([0], 0);
+        assert_eq!(trie.count_empty_key_entries(), 0);
+    }
+
+
+    #[test]
+    fn count_with_prefix() {
+        let mut trie = trie_of::<[u8; 2]>(&[[1, 2], [1, 3], [1, 4], [2, 0]]);
+        assert_eq!(trie.count_with_prefix([1]), 3);
+
+        trie.build_prefix_count_cache();
+        assert_eq!(trie.count_with_prefix([]), 4);
+        assert_eq!(trie.count_with_prefix([1]), 3);
+        assert_eq!(trie.count_with_prefix([2, 0]), 1);
+        assert_eq!(trie.count_with_prefix([3]), 0);
+
+        trie.try_remove([1, 3]).unwrap();
+        trie.insert([1, 5], ());
+        trie.insert([1, 6], ());
+        assert_eq!(trie.count_with_prefix([1]), 4);
+    }
+
+
+    #[test]
+    fn prefix_exists_exactly() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        trie.insert(b"aa", ());
+        trie.insert(b"ab", ());
+        trie.insert(b"ba", ());
+
+        assert_eq!(trie.prefix_exists_exactly(b"a"), (false, true));
+        assert_eq!(trie.prefix_exists_exactly(b"b"), (false, true));
+        assert_eq!(trie.prefix_exists_exactly(b"ba"), (true, false));
+        assert_eq!(trie.prefix_exists_exactly(b"c"), (false, false));
+        assert_eq!(trie.prefix_exists_exactly(b""), (false, true));
+    }
+
+
+    #[test]
+    fn len_at_prefix() {
+        let mut trie = trie_of::<[u8; 2]>(&[[1, 2], [1, 3], [2, 0]]);
+        assert_eq!(trie.len_at_prefix([]), 3);
+        assert_eq!(trie.len_at_prefix([1]), 2);
+
+        trie.build_prefix_count_cache();
+        assert_eq!(trie.len_at_prefix([1]), 2);
+        assert_eq!(trie.len_at_prefix([2, 1]), 0);
+    }
+
+
+    #[test]
+    fn prefix_iter_count() {
+        let mut trie = trie_of::<[u8; 2]>(&[[1, 2], [1, 3], [1, 4], [2, 0]]);
+        let prefixes = [&[][..], &[1], &[1, 3], &[2], &[3]];
+
+        for &prefix in &prefixes {
+            assert_eq!(trie.prefix_iter_count(prefix), trie.len_at_prefix(prefix));
+        }
+        assert_eq!(trie.prefix_iter_count([1]), 3);
+
+        trie.build_prefix_count_cache();
+        for &prefix in &prefixes {
+            assert_eq!(trie.prefix_iter_count(prefix),
+                       trie.iter().filter(|(key, _)| key.starts_with(prefix)).count());
+        }
+    }
+
+
+    #[test]
+    fn contains_range() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        trie.insert(b"bb", ());
+        trie.insert(b"dd", ());
+
+        let range = |start: &'static [u8], end: &'static [u8]| {
+            (Bound::Included(start), Bound::Excluded(end))
+        };
+
+        assert!(trie.contains_range(..));
+        assert!(trie.contains_range(range(b"ba", b"bc")));
+        assert!(trie.contains_range(range(b"bb", b"bc")));
+        assert!(!trie.contains_range(range(b"ba", b"bb")));
+        assert!(trie.contains_range((Bound::Included(&b"ba"[..]), Bound::Included(&b"bb"[..]))));
+        assert!(!trie.contains_range(range(b"bc", b"dd")));
+        assert!(!trie.contains_range((Bound::Excluded(&b"dd"[..]), Bound::Unbounded)));
+    }
+
+
+    #[test]
+    fn contains_range_seek() {
+        let mut next = random_bytes(13);
+
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        for _ in 0..150 {
+            trie.insert([next() & 0x35, next() & 0x73], ());
+
+            let start = [next() & 0x35, next() & 0x73];
+            let end = [next() & 0x35, next() & 0x73];
+            for start in &[&start[..], &start[..1], &[start[0], start[1], next()], &[]] {
+                for &(lower, upper) in &[(Bound::Included(*start), Bound::Excluded(&end[..])),
+                                         (Bound::Excluded(*start), Bound::Included(&end[..])),
+                                         (Bound::Excluded(*start), Bound::Unbounded)] {
+                    let expected = trie.iter().any(|(key, _)| (lower, upper).contains(&key[..]));
+                    assert_eq!(trie.contains_range((lower, upper)), expected);
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn contains_prefix() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert!(!trie.contains_prefix(b""));
+
+        trie.insert(b"abc", ());
+        trie.insert(b"abd", ());
+        trie.insert(b"xyz", ());
+
+        assert!(trie.contains_prefix(b""));
+        assert!(trie.contains_prefix(b"a"));
+        assert!(trie.contains_prefix(b"ab"));
+        assert!(trie.contains_prefix(b"abd"));
+        assert!(trie.contains_prefix(b"xy"));
+        assert!(!trie.contains_prefix(b"ac"));
+        assert!(!trie.contains_prefix(b"abe"));
+        assert!(!trie.contains_prefix(b"abcd"));
+        assert!(!trie.contains_prefix(b"b"));
+    }
+
+
+    #[test]
+    fn prefix_overlap() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ca"]);
+        let other = trie_of::<[u8; 2]>(&[b"ac", b"ba", b"cb"]);
+        let empty = Trie::<[u8; 2], ()>::new();
+
+        assert!(trie.prefix_overlap(&other, b""));
+        assert!(trie.prefix_overlap(&other, b"a"));
+        assert!(trie.prefix_overlap(&other, b"c"));
+        assert!(!trie.prefix_overlap(&other, b"b"));
+        assert!(!other.prefix_overlap(&trie, b"b"));
+
+        // A whole key counts as a prefix of itself, but only if both tries hold it.
+        assert!(!trie.prefix_overlap(&other, b"aa"));
+        assert!(trie.prefix_overlap(&trie, b"aa"));
+        assert!(!trie.prefix_overlap(&empty, b""));
+        assert!(!empty.prefix_overlap(&trie, b""));
+    }
+
+
+    #[test]
+    fn nearest_neighbor() {
+        let mut trie = Trie::<[u8; 3], u32>::new();
+        assert_eq!(trie.nearest_neighbor(b"abc"), None);
+
+        trie.insert(b"abc", 1);
+        trie.insert(b"abd", 2);
+        trie.insert(b"xyz", 3);
+
+        assert_eq!(trie.nearest_neighbor(b"abd"), Some((b"abd", &2)));
+        assert_eq!(trie.nearest_neighbor(b"abc"), Some((b"abc", &1)));
+        assert_eq!(trie.nearest_neighbor(b"xaa"), Some((b"xyz", &3)));
+        assert_eq!(trie.nearest_neighbor(b"x"), Some((b"xyz", &3)));
+    }
+
+
+    #[test]
+    fn consume_matching() {
+        let mut trie = Trie::<[u8; 2], u8>::new();
+        for (i, key) in [b"aa", b"ab", b"ba"].iter().enumerate() {
+            trie.insert(key, i as u8);
+        }
+
+        let mut consumed = Vec::new();
+        trie.consume_matching(b"a", |key, val| consumed.push((key, val)));
+        assert_eq!(consumed, vec![(*b"aa", 0), (*b"ab", 1)]);
+        assert_eq!(keys(&trie), vec![*b"ba"]);
+        assert_eq!(trie.len(), 1);
+    }
+
+
+    #[test]
+    fn partition_in_place() {
+        let mut trie = trie_of::<[u8; 3]>(&[b"abc", b"abd", b"acd", b"bcd"]);
+
+        let partition = trie.partition_in_place(b"ab");
+        assert_eq!(keys(&partition), vec![*b"abc", *b"abd"]);
+        assert_eq!(keys(&trie), vec![*b"acd", *b"bcd"]);
+
+        let partition = trie.partition_in_place(b"ab");
+        assert_eq!(keys(&partition), Vec::<[u8; 3]>::new());
+
+        let partition = trie.partition_in_place(b"");
+        assert_eq!(keys(&partition), vec![*b"acd", *b"bcd"]);
+        assert_eq!(keys(&trie), Vec::<[u8; 3]>::new());
+    }
+
+
+    #[test]
+    fn prefixes_len_distribution() {
+        let mut trie = Trie::<[u8; 3], ()>::new();
+        assert!(trie.prefixes_len_distribution().is_empty());
+
+        trie = trie_of(&[b"abc", b"abd", b"acd", b"bcd"]);
+
+        assert_eq!(trie.prefixes_len_distribution().into_iter().collect::<Vec<_>>(),
+                   vec![(1, 2), (2, 3), (3, 4)]);
+
+        trie = trie_of(&[b"abc"]);
+        assert_eq!(trie.prefixes_len_distribution().into_iter().collect::<Vec<_>>(),
+                   vec![(1, 1), (2, 1), (3, 1)]);
+    }
+
+
+    #[test]
+    fn frequency_map() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ba", b"ca", b"cb", b"cc"]);
+
+        let frequencies = trie.frequency_map(1);
+        assert_eq!(frequencies.into_iter().collect::<Vec<_>>(),
+                   vec![(b"a".to_vec(), 2), (b"b".to_vec(), 1), (b"c".to_vec(), 3)]);
+        assert_eq!(trie.frequency_map(0).get(&vec![]), Some(&6));
+        assert!(trie.frequency_map(3).is_empty());
+    }
+
+
+    #[test]
+    fn most_common_prefix() {
+        let mut trie = Trie::<[u8; 2], ()>::new();
+        assert_eq!(trie.most_common_prefix(1), None);
+
+        trie = trie_of(&[b"aa", b"ab", b"ba", b"ca", b"cb", b"cc"]);
+
+        assert_eq!(trie.most_common_prefix(1), Some(b"c".to_vec()));
+        assert_eq!(trie.most_common_prefix(2), Some(b"aa".to_vec()));
+        assert_eq!(trie.most_common_prefix(3), None);
+    }
+
+
+    #[test]
+    fn prefix_ranges_iter() {
+        let trie = trie_of::<[u8; 2]>(&[b"aa", b"ab", b"ba", b"ca", b"cb", b"cc"]);
+
+        let ranges = trie.prefix_ranges_iter(1)
+            .map(|(prefix, subtrie)| {
+                     (prefix, subtrie.iter().map(|(key, _)| *key).collect::<Vec<_>>())
+                 })
+            .collect::<Vec<_>>();
+        assert_eq!(ranges,
+                   vec![(b"a".to_vec(), vec![*b"aa", *b"ab"]),
+                        (b"b".to_vec(), vec![*b"ba"]),
+                        (b"c".to_vec(), vec![*b"ca", *b"cb", *b"cc"])]);
+
+        assert_eq!(trie.prefix_ranges_iter(0).map(|(_, subtrie)| subtrie.len()).sum::<usize>(),
+                   6);
+        assert_eq!(trie.prefix_ranges_iter(3).count(), 0);
+    }
+
+
+    #[test]
+    fn prefixes_of() {
+        let mut trie = Trie::<[u8; 2], u32>::new();
+        trie.insert(b"ab", 1);
+        trie.insert(b"ac", 2);
+
+        assert_eq!(trie.prefixes_of(b"abcd").collect::<Vec<_>>(), vec![(b"ab", &1)]);
+        assert_eq!(trie.prefixes_of(b"ac").collect::<Vec<_>>(), vec![(b"ac", &2)]);
+        assert_eq!(trie.prefixes_of(b"a").count(), 0);
+        assert_eq!(trie.prefixes_of(b"bc").count(), 0);
+    }
 }